@@ -0,0 +1,61 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use pvzol_tools_lib::amf::{amf0::{array, number, string}, packet::{IntoBytes, Packet}};
+
+/// `api.fuben.challenge` with a full team of plants
+fn challenge_packet() -> Packet {
+    let plant_ids = (1_900_000..1_900_500).map(|id| number(id as f64)).collect();
+    Packet::builder()
+        .with_default_version()
+        .body("api.fuben.challenge", "/1", array(vec![number(58), array(plant_ids)]))
+        .build()
+        .unwrap()
+}
+
+/// 40 `api.duty.reward` calls in a single packet
+fn batched_packet() -> Packet {
+    (1..=40).fold(Packet::builder().with_default_version(), |builder, i| {
+        builder.body("api.duty.reward", format!("/{}", i), array(vec![number(i), number(3), string("")]))
+    })
+        .build()
+        .unwrap()
+}
+
+/// how a packet was written before `SizeHint` was exact: every body grows its own buffer.
+fn into_bytes_growing(packet: Packet) -> Bytes {
+    let mut bytes = BytesMut::new();
+    bytes.put_u16(3);
+    bytes.put_u16(0);
+    bytes.put_u16(packet.bodies.len() as u16);
+    for body in packet.bodies {
+        let mut body_bytes = BytesMut::new();
+        body_bytes.put_u16(body.target_uri.len() as u16);
+        body_bytes.put(body.target_uri.as_bytes());
+        body_bytes.put_u16(body.response_uri.len() as u16);
+        body_bytes.put(body.response_uri.as_bytes());
+
+        let mut data = Vec::new();
+        body.data.write_to(&mut data).unwrap();
+        body_bytes.put_u32(data.len() as u32);
+        body_bytes.put(data.as_slice());
+
+        bytes.put(body_bytes);
+    }
+    bytes.freeze()
+}
+
+fn bench_into_bytes(c: &mut Criterion) {
+    for (name, packet) in [("challenge", challenge_packet()), ("batched", batched_packet())] {
+        let mut group = c.benchmark_group(name);
+        group.bench_function("into_bytes", |b| {
+            b.iter_batched(|| packet.clone(), |p| black_box(p.into_bytes()), BatchSize::SmallInput)
+        });
+        group.bench_function("growing", |b| {
+            b.iter_batched(|| packet.clone(), |p| black_box(into_bytes_growing(p)), BatchSize::SmallInput)
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_into_bytes);
+criterion_main!(benches);
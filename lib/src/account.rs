@@ -1,23 +1,156 @@
 use std::{collections::HashMap, path::Path};
 
-use crate::Result;
+use crate::{server::Server, transport::HttpOptions, ErrorKind, Result};
 
-use serde::Deserialize;
-use tokio::{fs::read};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use tokio::{fs::{read, write}};
 
-#[derive(Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
 pub struct AccountInfo {
-    pub server: usize,
-    pub cookies: HashMap<String, String>
+    /// `0` is unknown, e.g. the account of a client built from a url, and it is not saved.
+    #[serde(skip_serializing_if = "is_unknown_server")]
+    pub server: u8,
+    /// overrides the base url of the server, e.g. a mirror domain.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<Url>,
+    pub cookies: HashMap<String, String>,
+    /// servers which are not resolved by the default rule, see [`ServerRegistry`](crate::server::ServerRegistry).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub servers: Vec<Server>,
+    /// how the requests of the account look like, see [`HttpOptions`].
+    #[serde(default, skip_serializing_if = "HttpOptions::is_default")]
+    pub http: HttpOptions,
+    /// signs the php pages, see [`ClientBuilder::sig_key`](crate::ClientBuilder::sig_key).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sig_key: Option<String>,
+}
+
+fn is_unknown_server(server: &u8) -> bool {
+    *server == 0
 }
 
 impl AccountInfo {
     pub async fn from_file(file_name: impl AsRef<Path>) -> Result<AccountInfo> {
-        let file_content = read(file_name).await.map_err(|e| e.to_string())?;
+        let file_content = read(file_name).await?;
         AccountInfo::from_bytes(file_content.as_slice())
     }
 
     pub fn from_bytes<T: AsRef<[u8]>>(bytes: T) -> Result<AccountInfo> {
-        toml::from_slice(bytes.as_ref()).map_err(|e| e.to_string().into())
+        toml::from_slice(bytes.as_ref()).map_err(|e| ErrorKind::Config(e.to_string()))
+    }
+
+    /// write the cookies of the account into the config file, see [`patch_cookies`](Self::patch_cookies).
+    pub async fn save_cookies(&self, file_name: impl AsRef<Path>) -> Result<()> {
+        let file_name = file_name.as_ref();
+        let content = String::from_utf8(read(file_name).await?)
+            .map_err(|e| ErrorKind::Config(e.to_string()))?;
+        Ok(write(file_name, self.patch_cookies(&content)?).await?)
+    }
+
+    /// replace the values of the cookies in the `[cookies]` table of the toml document `content`,
+    /// and append the new ones to it. The rest of the document, e.g. comments, the order of the
+    /// keys or the cookies which the account does not have any more, is kept as it is.
+    pub fn patch_cookies(&self, content: &str) -> Result<String> {
+        let mut lines: Vec<String> = content.lines().map(str::to_owned).collect();
+        let mut missing: Vec<_> = self.cookies.iter().collect();
+        missing.sort();
+
+        let mut in_cookies = false;
+        // where the new cookies are inserted, after the last key of the `[cookies]` table.
+        let mut insert_at = None;
+        for (i, line) in lines.iter_mut().enumerate() {
+            let trimmed = line.trim_start();
+            if trimmed.starts_with('[') {
+                in_cookies = table_name(trimmed) == Some("cookies");
+                if in_cookies {
+                    insert_at = Some(i + 1);
+                }
+                continue;
+            }
+            if !in_cookies || trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            insert_at = Some(i + 1);
+            if let Some((key, rest)) = line.split_once('=') {
+                let name = unquote(key.trim());
+                if let Some(pos) = missing.iter().position(|(k, _)| **k == name) {
+                    let (_, value) = missing.remove(pos);
+                    *line = format!("{}= {}{}", key, toml_string(value), trailing_comment(rest));
+                }
+            }
+        }
+
+        if !missing.is_empty() {
+            let new = missing.iter().map(|(k, v)| format!("{} = {}", toml_key(k), toml_string(v)));
+            match insert_at {
+                Some(at) => {
+                    lines.splice(at..at, new);
+                },
+                None => {
+                    if matches!(lines.last(), Some(l) if !l.trim().is_empty()) {
+                        lines.push(String::new());
+                    }
+                    lines.push("[cookies]".to_owned());
+                    lines.extend(new);
+                },
+            }
+        }
+
+        let mut patched = lines.join("\n");
+        if content.ends_with('\n') || content.is_empty() {
+            patched.push('\n');
+        }
+        // e.g. the cookies are an inline table, which is not patched.
+        let saved = AccountInfo::from_bytes(&patched).map(|account| account.cookies);
+        if !matches!(&saved, Ok(cookies) if self.cookies.iter().all(|(k, v)| cookies.get(k) == Some(v))) {
+            return Err(ErrorKind::Config("无法更新配置文件中的cookie, 请将其写在`[cookies]`表中".to_owned()));
+        }
+        Ok(patched)
+    }
+}
+
+/// the name of the table header `[name]`, `None` for an array of tables.
+fn table_name(header: &str) -> Option<&str> {
+    let name = header.strip_prefix('[')?;
+    if name.starts_with('[') {
+        return None;
+    }
+    Some(name.split(']').next()?.trim())
+}
+
+fn unquote(key: &str) -> &str {
+    key.strip_prefix('"').and_then(|k| k.strip_suffix('"'))
+        .or_else(|| key.strip_prefix('\'').and_then(|k| k.strip_suffix('\'')))
+        .unwrap_or(key)
+}
+
+/// the comment after a string value, e.g. ` # expires in a week`.
+fn trailing_comment(value: &str) -> &str {
+    let value = value.trim_start();
+    let quote = match value.chars().next() {
+        Some(q @ ('"' | '\'')) => q,
+        _ => return "",
+    };
+    let mut escaped = false;
+    for (i, c) in value.char_indices().skip(1) {
+        match c {
+            '\\' if quote == '"' => escaped = !escaped,
+            c if c == quote && !escaped => return value[i + 1..].trim_end(),
+            _ => escaped = false,
+        }
+    }
+    ""
+}
+
+fn toml_string(s: &str) -> String {
+    toml::Value::String(s.to_owned()).to_string()
+}
+
+fn toml_key(key: &str) -> String {
+    if !key.is_empty() && key.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-') {
+        key.to_owned()
+    } else {
+        toml_string(key)
     }
 }
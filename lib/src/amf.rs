@@ -3,8 +3,12 @@
 use std::collections::HashMap;
 
 pub use amf::{amf0, amf3, Amf0Value, Amf3Value, Value, Version};
+pub use de::{from_value, Error};
+pub use ser::to_value;
 
+pub mod de;
 pub mod packet;
+pub mod ser;
 
 pub trait TryIntoAmf0Object {
     fn try_into_amf0_object(self) -> Option<HashMap<String, Amf0Value>>;
@@ -14,6 +18,10 @@ pub trait TryAsAmf0Object {
     fn try_as_amf0_object(&self) -> Option<HashMap<&str, &Amf0Value>>;
 }
 
+pub trait TryAsAmf3Object {
+    fn try_as_amf3_object(&self) -> Option<HashMap<&str, &Amf3Value>>;
+}
+
 pub trait TryAsNumber {
     fn try_as_number(&self) -> Option<f64>;
 }
@@ -40,7 +48,7 @@ impl TryIntoAmf0Object for Value {
 impl TryAsAmf0Object for Amf0Value {
     fn try_as_amf0_object(&self) -> Option<HashMap<&str, &Amf0Value>> {
         if let Amf0Value::Object{entries,..} = self {
-            let entries = entries.into_iter()
+            let entries = entries.iter()
                 .map(|p| (p.key.as_str(), &p.value));
             return Some(HashMap::from_iter(entries));
         }
@@ -50,42 +58,130 @@ impl TryAsAmf0Object for Amf0Value {
 
 impl TryAsAmf0Object for Value {
     fn try_as_amf0_object(&self) -> Option<HashMap<&str, &Amf0Value>> {
-        if let Value::Amf0(Amf0Value::Object{entries,..}) = self {
-            let entries = entries.into_iter()
-                .map(|p| (p.key.as_str(), &p.value));
-            return Some(HashMap::from_iter(entries));
+        match self {
+            Value::Amf0(val) => val.try_as_amf0_object(),
+            Value::Amf3(_) => None,
+        }
+    }
+}
+
+impl TryAsAmf3Object for Amf3Value {
+    fn try_as_amf3_object(&self) -> Option<HashMap<&str, &Amf3Value>> {
+        match self {
+            Amf3Value::Object{entries,..}
+            | Amf3Value::Array{assoc_entries: entries,..} => {
+                let entries = entries.iter()
+                    .map(|p| (p.key.as_str(), &p.value));
+                Some(HashMap::from_iter(entries))
+            },
+            _ => None,
+        }
+    }
+}
+
+impl TryAsAmf3Object for Amf0Value {
+    fn try_as_amf3_object(&self) -> Option<HashMap<&str, &Amf3Value>> {
+        if let Amf0Value::AvmPlus(val) = self {
+            return val.try_as_amf3_object();
         }
         None
     }
 }
 
+impl TryAsAmf3Object for Value {
+    fn try_as_amf3_object(&self) -> Option<HashMap<&str, &Amf3Value>> {
+        match self {
+            Value::Amf0(val) => val.try_as_amf3_object(),
+            Value::Amf3(val) => val.try_as_amf3_object(),
+        }
+    }
+}
+
 impl TryAsNumber for Amf0Value {
     fn try_as_number(&self) -> Option<f64> {
-        if let Amf0Value::Number(num)= self {
-            return Some(*num);
+        match self {
+            Amf0Value::Number(num) => Some(*num),
+            Amf0Value::AvmPlus(val) => val.try_as_number(),
+            _ => None,
+        }
+    }
+}
+
+impl TryAsNumber for Amf3Value {
+    fn try_as_number(&self) -> Option<f64> {
+        match self {
+            Amf3Value::Integer(num) => Some(*num as f64),
+            Amf3Value::Double(num) => Some(*num),
+            _ => None,
+        }
+    }
+}
+
+impl TryAsNumber for Value {
+    fn try_as_number(&self) -> Option<f64> {
+        match self {
+            Value::Amf0(val) => val.try_as_number(),
+            Value::Amf3(val) => val.try_as_number(),
         }
-        None
     }
 }
 
 impl TryAsBoolean for Amf0Value {
     fn try_as_bool(&self) -> Option<bool> {
-        if let Amf0Value::Boolean(b)= self {
+        match self {
+            Amf0Value::Boolean(b) => Some(*b),
+            Amf0Value::AvmPlus(val) => val.try_as_bool(),
+            _ => None,
+        }
+    }
+}
+
+impl TryAsBoolean for Amf3Value {
+    fn try_as_bool(&self) -> Option<bool> {
+        if let Amf3Value::Boolean(b) = self {
             return Some(*b);
         }
         None
     }
 }
 
+impl TryAsBoolean for Value {
+    fn try_as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Amf0(val) => val.try_as_bool(),
+            Value::Amf3(val) => val.try_as_bool(),
+        }
+    }
+}
+
 impl TryAsStr for Amf0Value {
     fn try_as_str(&self) -> Option<&str> {
-        if let Amf0Value::String(s)= self {
+        match self {
+            Amf0Value::String(s) => Some(s.as_str()),
+            Amf0Value::AvmPlus(val) => TryAsStr::try_as_str(val),
+            _ => None,
+        }
+    }
+}
+
+impl TryAsStr for Amf3Value {
+    fn try_as_str(&self) -> Option<&str> {
+        if let Amf3Value::String(s) = self {
             return Some(s.as_str());
         }
         None
     }
 }
 
+impl TryAsStr for Value {
+    fn try_as_str(&self) -> Option<&str> {
+        match self {
+            Value::Amf0(val) => TryAsStr::try_as_str(val),
+            Value::Amf3(val) => TryAsStr::try_as_str(val),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::vec;
@@ -94,7 +190,7 @@ mod test {
     use super::packet::*;
 
     use amf0::{array, number, object, string};
-    use bytes::Bytes;
+    use bytes::{Buf, Bytes};
 
     #[test]
     fn test_serialize() -> Result<(), Box<dyn std::error::Error>>{
@@ -120,13 +216,13 @@ mod test {
             print!("{:02x?} ", byte);
             if (i+1)%8 == 0 {
                 if (i+1)%16 == 0 {
-                    print!("\n");
+                    println!();
                 } else {
                     print!("  ");
                 }
             }
         }
-        println!("");
+        println!();
 
         Ok(())
     }
@@ -144,5 +240,328 @@ mod test {
 
         Ok(())
     }
-}
 
+    #[test]
+    fn test_deser_amf3() -> crate::Result<(), Box<dyn std::error::Error>>{
+        let data = Value::Amf0(Amf0Value::AvmPlus(amf3::Value::Object {
+            class_name: None,
+            sealed_count: 0,
+            entries: vec![
+                amf::Pair { key: "now_id".to_owned(), value: amf3::Value::Integer(587) },
+                amf::Pair { key: "name".to_owned(), value: amf3::Value::String("Mike".to_owned()) },
+            ],
+        }));
+        let mut bytes = Packet::builder()
+            .with_default_version()
+            .body("/1/onResult", "null", data)
+            .build()?
+            .into_bytes();
+
+        let packet: Packet = bytes.read_as()?;
+        let data = &packet.bodies[0].data;
+        assert!(matches!(data, Value::Amf3(_)));
+
+        let data = data.try_as_amf3_object().ok_or("not an amf3 object")?;
+        assert_eq!(data.get("now_id").and_then(|v| v.try_as_number()), Some(587.));
+        assert_eq!(data.get("name").and_then(|v| TryAsStr::try_as_str(*v)), Some("Mike"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deser_raw_amf3() -> crate::Result<(), Box<dyn std::error::Error>>{
+        /// an `amf3` packet with a single body of the raw `data`.
+        fn packet_of(data: &[u8]) -> Vec<u8> {
+            let mut packet = vec![0x00, 0x03, 0x00, 0x00, 0x00, 0x01];
+            for uri in ["/1/onResult", "null"] {
+                packet.extend_from_slice(&(uri.len() as u16).to_be_bytes());
+                packet.extend_from_slice(uri.as_bytes());
+            }
+            packet.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            packet.extend_from_slice(data);
+            packet
+        }
+
+        // `0x06` is also the `amf0` undefined
+        let packet: Packet = packet_of(b"\x06\x09Mike").as_slice().read_as()?;
+        assert!(matches!(&packet.bodies[0].data, Value::Amf3(amf3::Value::String(s)) if s == "Mike"));
+
+        // `0x05` is also the `amf0` null
+        let mut data = vec![0x05];
+        data.extend_from_slice(&1.5f64.to_be_bytes());
+        let packet: Packet = packet_of(&data).as_slice().read_as()?;
+        assert!(matches!(&packet.bodies[0].data, Value::Amf3(amf3::Value::Double(d)) if *d == 1.5));
+
+        // a whole `amf0` value is still `amf0`
+        let packet: Packet = packet_of(&[0x05]).as_slice().read_as()?;
+        assert!(matches!(&packet.bodies[0].data, Value::Amf0(Amf0Value::Null)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_deser_owned() -> crate::Result<(), Box<dyn std::error::Error>>{
+        fn assert_owned<T: Send + 'static>(_: &T) {}
+
+        let packet: Packet = {
+            let mut resp = Bytes::copy_from_slice(include_bytes!("../test_resp.amf"));
+            resp.read_as()?
+        };
+        assert_owned(&packet);
+        assert_eq!(packet.bodies[0].target_uri, "/1/onResult");
+
+        // the target uri of the request is `api.apiorganism.qualityUp`,
+        // break it with an invalid utf-8 sequence.
+        let mut req = include_bytes!("../test_req.amf").to_vec();
+        req[8] = 0xff;
+        let res: Result<Packet, _> = Bytes::from(req).read_as();
+        assert!(res.is_err());
+
+        Ok(())
+    }
+
+    /// a `VecDeque` whose bytes wrap around its buffer, so `chunk` is only a part of them.
+    fn wrap(bytes: &[u8]) -> std::collections::VecDeque<u8> {
+        let mut deque = std::collections::VecDeque::with_capacity(bytes.len());
+        let (head, tail) = bytes.split_at(20);
+        deque.extend(tail);
+        head.iter().rev().for_each(|&b| deque.push_front(b));
+        assert!(deque.chunk().len() < deque.remaining());
+        deque
+    }
+
+    #[test]
+    fn test_packet_error() {
+        use packet::{Field, PacketError, Section};
+
+        // truncated in the middle of the data of the only body.
+        let resp = include_bytes!("../test_resp.amf");
+        let mut truncated = &resp[..resp.len() - 10];
+        let e = ReadAs::<Packet>::read_as(&mut truncated).unwrap_err();
+        assert!(matches!(e, PacketError::UnexpectedEof { .. }));
+        assert_eq!(e.location().section, Section::Body(0));
+        assert_eq!(e.location().field, Field::Data);
+        // the input is not consumed on error.
+        assert_eq!(truncated.len(), resp.len() - 10);
+
+        // not contiguous, also untouched on error
+        let mut wrapped = wrap(&resp[..resp.len() - 10]);
+        let e = ReadAs::<Packet>::read_as(&mut wrapped).unwrap_err();
+        assert!(matches!(e, PacketError::UnexpectedEof { .. }));
+        assert_eq!(wrapped.remaining(), resp.len() - 10);
+        let mut wrapped = wrap(resp);
+        assert!(ReadAs::<Packet>::read_as(&mut wrapped).is_ok());
+        assert_eq!(wrapped.remaining(), 0);
+        // the bytes after the packet are left in `src`
+        let mut wrapped = wrap(&[&resp[..], b"rest"].concat());
+        assert!(ReadAs::<Packet>::read_as(&mut wrapped).is_ok());
+        assert_eq!(wrapped, b"rest");
+
+        // an html error page
+        let mut html = &b"<html><body>502 Bad Gateway</body></html>"[..];
+        let e = ReadAs::<Packet>::read_as(&mut html).unwrap_err();
+        assert!(matches!(e, PacketError::UnknownVersion { version: 0x3c68, .. }));
+        assert_eq!(e.location().offset, 0);
+        assert_eq!(e.location().section, Section::Preamble);
+        assert!(e.location().snippet.starts_with("00000000:[3c]68 74 6d"));
+        assert!(e.location().snippet.contains("<html><body>502 "));
+
+        // the target uri of the request is broken at byte 8
+        let mut req = include_bytes!("../test_req.amf").to_vec();
+        req[8] = 0xff;
+        let e = ReadAs::<Packet>::read_as(&mut req.as_slice()).unwrap_err();
+        assert!(matches!(e, PacketError::InvalidUtf8 { .. }));
+        assert_eq!(e.location().section, Section::Body(0));
+        assert_eq!(e.location().field, Field::TargetUri);
+        assert_eq!(e.location().offset, 8);
+
+        // broken data
+        let resp = Packet::builder()
+            .with_default_version()
+            .body("/1/onResult", "null", Amf0Value::Null)
+            .build()
+            .unwrap()
+            .into_bytes();
+        let mut resp = resp.to_vec();
+        let last = resp.len() - 1;
+        resp[last] = 0xfe;
+        let e = ReadAs::<Packet>::read_as(&mut resp.as_slice()).unwrap_err();
+        assert!(matches!(e, PacketError::InvalidData { .. }));
+        assert_eq!(e.location().offset, last);
+        assert!(e.to_string().contains("body #0"));
+    }
+
+    #[test]
+    fn test_take_response() {
+        let fault = object(vec![
+            ("faultCode", string("AMFPHP_RUNTIME_ERROR")),
+            ("faultString", string("Method does not exist")),
+        ].into_iter());
+        let mut packet = Packet::builder()
+            .version(Version::Amf0)
+            .body("/2/onResult", "null", number(1.))
+            .body("/1/onStatus", "null", fault)
+            .body("/10/onResult", "null", number(10.))
+            .build()
+            .unwrap();
+
+        assert_eq!(packet.take_response("/3"), None);
+        assert_eq!(packet.take_response("/10"), Some(packet::Response::Result(number(10.).into())));
+        assert_eq!(packet.take_response("/2"), Some(packet::Response::Result(number(1.).into())));
+
+        let status = match packet.take_response("/1") {
+            Some(packet::Response::Status(data)) => data,
+            other => panic!("unexpected response: {:?}", other),
+        };
+        let fault = packet::RemoteFault::from_status(&status);
+        assert_eq!(fault.fault_code, "AMFPHP_RUNTIME_ERROR");
+        assert_eq!(fault.fault_string, "Method does not exist");
+        assert_eq!(fault.fault_detail, "");
+        assert!(packet.bodies.is_empty());
+
+        // amfphp 1.x style
+        let status = object(vec![
+            ("code", number(1.)),
+            ("description", string("数据不存在")),
+            ("details", string("api.php")),
+        ].into_iter()).into();
+        let fault = packet::RemoteFault::from_status(&status);
+        assert_eq!(fault.fault_code, "1");
+        assert_eq!(fault.fault_string, "数据不存在");
+        assert_eq!(fault.fault_detail, "api.php");
+    }
+
+    #[test]
+    fn test_size_hint() {
+        use std::time::Duration;
+        use amf::Pair;
+
+        fn pair<V>(key: &str, value: V) -> Pair<String, V> {
+            Pair { key: key.to_owned(), value }
+        }
+
+        let amf3_values = vec![
+            amf3::Value::Undefined,
+            amf3::Value::Boolean(true),
+            amf3::Value::Integer(0x7f),
+            amf3::Value::Integer(0x4000),
+            amf3::Value::Integer(-1),
+            amf3::Value::Double(1.5),
+            amf3::Value::String("a".repeat(200)),
+            amf3::Value::Xml("<a/>".to_owned()),
+            amf3::Value::Date { unix_time: Duration::from_secs(1660639233) },
+            amf3::Value::Array {
+                assoc_entries: vec![pair("key", amf3::Value::Null)],
+                dense_entries: vec![amf3::Value::Integer(1); 100],
+            },
+            amf3::Value::Object {
+                class_name: Some("organism".to_owned()),
+                sealed_count: 1,
+                entries: vec![
+                    pair("id", amf3::Value::Integer(1996336)),
+                    pair("name", amf3::Value::String("豌豆射手".to_owned())),
+                ],
+            },
+            amf3::Value::Object {
+                class_name: None,
+                sealed_count: 0,
+                entries: vec![],
+            },
+            amf3::Value::ByteArray(vec![0; 300]),
+            amf3::Value::IntVector { is_fixed: true, entries: vec![-1, 2] },
+            amf3::Value::UintVector { is_fixed: false, entries: vec![1, 2, 3] },
+            amf3::Value::DoubleVector { is_fixed: false, entries: vec![1.] },
+            amf3::Value::ObjectVector { class_name: None, is_fixed: false, entries: vec![amf3::Value::Null] },
+            amf3::Value::Dictionary {
+                is_weak: false,
+                entries: vec![Pair { key: amf3::Value::Integer(1), value: amf3::Value::Boolean(false) }],
+            },
+        ];
+        let mut values: Vec<Value> = vec![
+            number(1996336).into(),
+            Amf0Value::Boolean(false).into(),
+            string("劣质").into(),
+            string("a".repeat(0x10000)).into(),
+            Amf0Value::Null.into(),
+            Amf0Value::Object {
+                class_name: Some("data.web.pvz_s6.app.services.client.organism".to_owned()),
+                entries: vec![pair("grade", string("102"))],
+            }.into(),
+            Amf0Value::EcmaArray { entries: vec![pair("0", number(1))] }.into(),
+            array(vec![number(1), array(vec![number(2); 40])]).into(),
+            Amf0Value::Date { unix_time: Duration::from_secs(1660639233), time_zone: 0 }.into(),
+            Amf0Value::XmlDocument("<a/>".to_owned()).into(),
+            Amf0Value::AvmPlus(amf3_values[10].clone()).into(),
+        ];
+        values.extend(amf3_values.into_iter().map(Value::Amf3));
+
+        for value in values {
+            let mut buf = Vec::new();
+            value.write_to(&mut buf).unwrap();
+            assert_eq!(value.size_hint(), buf.len(), "{:?}", value);
+        }
+
+        let packet = Packet::builder()
+            .with_default_version()
+            .header("AppendToGatewayUrl", false, string("?PHPSESSID=1").into())
+            .body("api.apiorganism.qualityUp", "/1", array(vec![number(1996336)]))
+            .body("api.apiorganism.skillUp", "/2", array(vec![number(1996336), number(586)]))
+            .build()
+            .unwrap();
+        let size_hint = packet.size_hint();
+        assert_eq!(packet.into_bytes().len(), size_hint);
+    }
+
+    #[test]
+    fn test_serde() -> crate::Result<(), Box<dyn std::error::Error>>{
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Serialize)]
+        struct Args<'a> {
+            name: &'a str,
+            ids: Vec<u32>,
+        }
+
+        let args = to_value(&(1996336., Some("medal"), None::<f64>))?;
+        assert_eq!(args, array(vec![number(1996336), string("medal"), Amf0Value::Null]));
+
+        let args = to_value(&Args { name: "Mike", ids: vec![999, 1001] })?;
+        assert_eq!(args, object([
+            ("name", string("Mike")),
+            ("ids", array(vec![number(999), number(1001)])),
+        ].into_iter()));
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Organism {
+            id: u64,
+            grade: u32,
+            owner: String,
+            pullulation: f64,
+            #[serde(rename = "isSteal")]
+            is_steal: bool,
+            quality: Option<String>,
+        }
+
+        let mut resp = Bytes::from_static(include_bytes!("../test_resp.amf"));
+        let packet: Packet = resp.read_as()?;
+        let organism: Organism = from_value(&packet.bodies[0].data)?;
+        assert_eq!(organism.id, 1890356);
+        assert_eq!(organism.grade, 102);
+        assert!(!organism.is_steal);
+
+        let amf3_data = Value::Amf3(amf3::Value::Object {
+            class_name: None,
+            sealed_count: 0,
+            entries: vec![
+                amf::Pair { key: "id".to_owned(), value: amf3::Value::String("1890356".to_owned()) },
+                amf::Pair { key: "grade".to_owned(), value: amf3::Value::Integer(102) },
+                amf::Pair { key: "owner".to_owned(), value: amf3::Value::String(organism.owner.clone()) },
+                amf::Pair { key: "pullulation".to_owned(), value: amf3::Value::Double(organism.pullulation) },
+                amf::Pair { key: "isSteal".to_owned(), value: amf3::Value::Integer(0) },
+                amf::Pair { key: "quality".to_owned(), value: amf3::Value::Null },
+            ],
+        });
+        assert_eq!(from_value::<Organism>(&amf3_data)?, organism);
+
+        Ok(())
+    }
+}
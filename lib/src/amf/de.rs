@@ -0,0 +1,408 @@
+//! deserialize rust values from `amf0`/`amf3` values.
+//!
+//! The game server is loose about types, e.g. ids and grades are often sent as strings,
+//! so numbers and booleans are also parsed from strings, and strings are also read from numbers.
+
+use std::fmt;
+
+use amf::{Amf0Value, Amf3Value, Pair};
+use serde::de::{
+    self, Deserialize, DeserializeSeed, IntoDeserializer, Visitor,
+    value::{BorrowedStrDeserializer, SeqDeserializer},
+};
+
+use super::Value;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// error of serializing or deserializing an amf value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+impl serde::ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+impl From<&str> for Error {
+    fn from(s: &str) -> Self {
+        Error(s.to_owned())
+    }
+}
+
+/// deserialize `T` from an amf value, e.g. the data of a response body.
+pub fn from_value<'de, T: Deserialize<'de>>(value: &'de Value) -> Result<T> {
+    T::deserialize(Deserializer::from(value))
+}
+
+/// A borrowed `amf0` or `amf3` value.
+#[derive(Clone, Copy)]
+pub enum Deserializer<'de> {
+    Amf0(&'de Amf0Value),
+    Amf3(&'de Amf3Value),
+}
+
+impl<'de> From<&'de Value> for Deserializer<'de> {
+    fn from(value: &'de Value) -> Self {
+        match value {
+            Value::Amf0(val) => Deserializer::from(val),
+            Value::Amf3(val) => Deserializer::Amf3(val),
+        }
+    }
+}
+
+impl<'de> From<&'de Amf0Value> for Deserializer<'de> {
+    fn from(value: &'de Amf0Value) -> Self {
+        match value {
+            Amf0Value::AvmPlus(val) => Deserializer::Amf3(val),
+            val => Deserializer::Amf0(val),
+        }
+    }
+}
+
+impl<'de> From<&'de Amf3Value> for Deserializer<'de> {
+    fn from(value: &'de Amf3Value) -> Self {
+        Deserializer::Amf3(value)
+    }
+}
+
+impl<'de> Deserializer<'de> {
+    fn as_str(&self) -> Option<&'de str> {
+        match *self {
+            Deserializer::Amf0(Amf0Value::String(s) | Amf0Value::XmlDocument(s)) => Some(s),
+            Deserializer::Amf3(
+                Amf3Value::String(s) | Amf3Value::XmlDocument(s) | Amf3Value::Xml(s)
+            ) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Deserializer::Amf0(Amf0Value::Number(n)) => Some(*n),
+            Deserializer::Amf3(Amf3Value::Integer(n)) => Some(*n as f64),
+            Deserializer::Amf3(Amf3Value::Double(n)) => Some(*n),
+            _ => None,
+        }
+    }
+
+    fn is_null(&self) -> bool {
+        matches!(
+            self,
+            Deserializer::Amf0(Amf0Value::Null | Amf0Value::Undefined)
+            | Deserializer::Amf3(Amf3Value::Null | Amf3Value::Undefined)
+        )
+    }
+
+    fn invalid_type(&self, exp: &dyn de::Expected) -> Error {
+        let unexp = match *self {
+            Deserializer::Amf0(val) => format!("amf0 value {:?}", val),
+            Deserializer::Amf3(val) => format!("amf3 value {:?}", val),
+        };
+        de::Error::invalid_type(de::Unexpected::Other(&unexp), exp)
+    }
+
+    /// visit a number, integers are visited as integers to satisfy integer fields.
+    fn visit_number<V: Visitor<'de>>(n: f64, visitor: V) -> Result<V::Value> {
+        if n.fract() == 0. && n >= 0. && n <= u64::MAX as f64 {
+            visitor.visit_u64(n as u64)
+        } else if n.fract() == 0. && n >= i64::MIN as f64 && n < 0. {
+            visitor.visit_i64(n as i64)
+        } else {
+            visitor.visit_f64(n)
+        }
+    }
+
+    fn parse_number<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if let Some(s) = self.as_str() {
+            let n = s.trim()
+                .parse::<f64>()
+                .map_err(|_| <Error as de::Error>::invalid_value(de::Unexpected::Str(s), &visitor))?;
+            return Self::visit_number(n, visitor);
+        }
+        de::Deserializer::deserialize_any(self, visitor)
+    }
+}
+
+macro_rules! deserialize_number {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+                self.parse_number(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            Deserializer::Amf0(val) => match val {
+                Amf0Value::Number(n) => Self::visit_number(*n, visitor),
+                Amf0Value::Boolean(b) => visitor.visit_bool(*b),
+                Amf0Value::String(s) | Amf0Value::XmlDocument(s) => visitor.visit_borrowed_str(s),
+                Amf0Value::Object { entries, .. }
+                | Amf0Value::EcmaArray { entries } => visitor.visit_map(MapAccess::new(entries)),
+                Amf0Value::Array { entries } => visitor.visit_seq(SeqAccess::new(entries)),
+                Amf0Value::Null | Amf0Value::Undefined => visitor.visit_unit(),
+                Amf0Value::Date { unix_time, .. } => visitor.visit_u64(unix_time.as_millis() as u64),
+                Amf0Value::AvmPlus(val) => Deserializer::Amf3(val).deserialize_any(visitor),
+            },
+            Deserializer::Amf3(val) => match val {
+                Amf3Value::Undefined | Amf3Value::Null => visitor.visit_unit(),
+                Amf3Value::Boolean(b) => visitor.visit_bool(*b),
+                Amf3Value::Integer(n) => visitor.visit_i32(*n),
+                Amf3Value::Double(n) => Self::visit_number(*n, visitor),
+                Amf3Value::String(s)
+                | Amf3Value::XmlDocument(s)
+                | Amf3Value::Xml(s) => visitor.visit_borrowed_str(s),
+                Amf3Value::Date { unix_time } => visitor.visit_u64(unix_time.as_millis() as u64),
+                Amf3Value::Array { assoc_entries, dense_entries } => {
+                    if assoc_entries.is_empty() {
+                        visitor.visit_seq(SeqAccess::new(dense_entries))
+                    } else {
+                        visitor.visit_map(MapAccess::new(assoc_entries))
+                    }
+                },
+                Amf3Value::Object { entries, .. } => visitor.visit_map(MapAccess::new(entries)),
+                Amf3Value::ByteArray(bytes) => visitor.visit_borrowed_bytes(bytes),
+                Amf3Value::IntVector { entries, .. } =>
+                    visitor.visit_seq(SeqDeserializer::new(entries.iter().copied())),
+                Amf3Value::UintVector { entries, .. } =>
+                    visitor.visit_seq(SeqDeserializer::new(entries.iter().copied())),
+                Amf3Value::DoubleVector { entries, .. } =>
+                    visitor.visit_seq(SeqDeserializer::new(entries.iter().copied())),
+                Amf3Value::ObjectVector { entries, .. } => visitor.visit_seq(SeqAccess::new(entries)),
+                Amf3Value::Dictionary { entries, .. } => visitor.visit_map(MapAccess::new(entries)),
+            },
+        }
+    }
+
+    deserialize_number! {
+        deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_f32 deserialize_f64
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if let Some(s) = self.as_str() {
+            return match s.trim() {
+                "true" | "1" => visitor.visit_bool(true),
+                "false" | "0" | "" => visitor.visit_bool(false),
+                _ => Err(de::Error::invalid_value(de::Unexpected::Str(s), &visitor)),
+            };
+        }
+        if let Some(n) = self.as_f64() {
+            return visitor.visit_bool(n != 0.);
+        }
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if let Some(n) = self.as_f64() {
+            return visitor.visit_string(n.to_string());
+        }
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if self.is_null() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        if let Some(s) = self.as_str() {
+            return visitor.visit_enum(s.into_deserializer());
+        }
+        // `{ variant: value }`
+        let entries = match self {
+            Deserializer::Amf0(Amf0Value::Object { entries, .. }) => entries
+                .first()
+                .filter(|_| entries.len() == 1)
+                .map(|p| (p.key.as_str(), Deserializer::from(&p.value))),
+            Deserializer::Amf3(Amf3Value::Object { entries, .. }) => entries
+                .first()
+                .filter(|_| entries.len() == 1)
+                .map(|p| (p.key.as_str(), Deserializer::from(&p.value))),
+            _ => None,
+        };
+        match entries {
+            Some((variant, value)) => visitor.visit_enum(EnumAccess { variant, value }),
+            None => Err(self.invalid_type(&visitor)),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        char bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Deserializer<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+struct SeqAccess<'de, T> {
+    iter: std::slice::Iter<'de, T>,
+}
+
+impl<'de, T> SeqAccess<'de, T> {
+    fn new(entries: &'de [T]) -> Self {
+        SeqAccess { iter: entries.iter() }
+    }
+}
+
+impl<'de, T> de::SeqAccess<'de> for SeqAccess<'de, T>
+where
+    &'de T: Into<Deserializer<'de>>,
+{
+    type Error = Error;
+
+    fn next_element_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<Option<S::Value>> {
+        match self.iter.next() {
+            Some(val) => seed.deserialize(val.into()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct MapAccess<'de, K, V> {
+    iter: std::slice::Iter<'de, Pair<K, V>>,
+    value: Option<&'de V>,
+}
+
+impl<'de, K, V> MapAccess<'de, K, V> {
+    fn new(entries: &'de [Pair<K, V>]) -> Self {
+        MapAccess { iter: entries.iter(), value: None }
+    }
+}
+
+/// keys of objects are strings, while keys of `amf3` dictionaries are values.
+trait MapKey<'de> {
+    fn deserialize_key<S: DeserializeSeed<'de>>(&'de self, seed: S) -> Result<S::Value>;
+}
+
+impl<'de> MapKey<'de> for String {
+    fn deserialize_key<S: DeserializeSeed<'de>>(&'de self, seed: S) -> Result<S::Value> {
+        seed.deserialize(BorrowedStrDeserializer::new(self))
+    }
+}
+
+impl<'de> MapKey<'de> for Amf3Value {
+    fn deserialize_key<S: DeserializeSeed<'de>>(&'de self, seed: S) -> Result<S::Value> {
+        seed.deserialize(Deserializer::Amf3(self))
+    }
+}
+
+impl<'de, K, V> de::MapAccess<'de> for MapAccess<'de, K, V>
+where
+    K: MapKey<'de>,
+    &'de V: Into<Deserializer<'de>>,
+{
+    type Error = Error;
+
+    fn next_key_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<Option<S::Value>> {
+        match self.iter.next() {
+            Some(pair) => {
+                self.value = Some(&pair.value);
+                pair.key.deserialize_key(seed).map(Some)
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<S::Value> {
+        let value = self.value
+            .take()
+            .ok_or("`next_value` is called before `next_key`")?;
+        seed.deserialize(value.into())
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct EnumAccess<'de> {
+    variant: &'de str,
+    value: Deserializer<'de>,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess<'de> {
+    type Error = Error;
+    type Variant = Deserializer<'de>;
+
+    fn variant_seed<S: DeserializeSeed<'de>>(self, seed: S) -> Result<(S::Value, Self::Variant)> {
+        let variant = seed.deserialize(BorrowedStrDeserializer::<Error>::new(self.variant))?;
+        Ok((variant, self.value))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Deserializer<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        de::Deserialize::deserialize(self)
+    }
+
+    fn newtype_variant_seed<S: DeserializeSeed<'de>>(self, seed: S) -> Result<S::Value> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
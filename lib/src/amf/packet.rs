@@ -1,30 +1,30 @@
-use std::mem::{size_of, transmute};
+use std::mem::size_of;
 use std::ops::{Not};
 
 use crate::Result;
 use crate::amf::{Value, Version};
 use amf::{amf0, amf3};
-use bytes::{BufMut, Bytes, BytesMut, Buf, };
-use try_buf::{TryBuf,};
+use bytes::{BufMut, Bytes, Buf, };
 
-#[derive(Debug)]
-pub struct Packet<'a> {
+/// An owned amf packet, which can be stored or sent across tasks freely.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet {
     pub version: super::Version,
-    pub headers: Vec<Header<'a>>,
-    pub bodies: Vec<Body<'a>>,
+    pub headers: Vec<Header>,
+    pub bodies: Vec<Body>,
 }
 
-#[derive(Debug)]
-pub struct Header<'a> {
-    pub name: &'a str,
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub name: String,
     pub must_understand: bool,
     pub data: Value,
 }
 
-#[derive(Debug)]
-pub struct Body<'a> {
-    pub target_uri: &'a str,
-    pub response_uri: &'a str,
+#[derive(Debug, Clone, PartialEq)]
+pub struct Body {
+    pub target_uri: String,
+    pub response_uri: String,
     pub data: Value,
 }
 
@@ -50,7 +50,7 @@ impl VersionVal for Version {
     }
 }
 
-impl<'a> Packet<'a> {
+impl Packet {
     #[inline]
     pub(crate) fn size_hint(&self) -> usize {
         size_of::<u16>()            // version
@@ -66,24 +66,94 @@ impl<'a> Packet<'a> {
                 .sum::<usize>()
     }
 
-    pub fn builder() -> PacketBuilder<'a> {
+    pub fn builder() -> PacketBuilder {
         PacketBuilder::new()
     }
 
-    pub fn read_from<'s,T: Buf>(src: &'s mut T) -> Result<Packet<'s>> {
-        src.read_as().map_err(|e: Box<dyn std::error::Error>| e.to_string().into())
+    pub fn read_from<T: Buf + Clone>(src: &mut T) -> Result<Packet> {
+        Ok(src.read_as()?)
+    }
+
+    /// Take the body answering the call sent with `response_uri`,
+    /// whose target uri is `{response_uri}/onResult` or `{response_uri}/onStatus`.
+    pub fn take_response(&mut self, response_uri: &str) -> Option<Response> {
+        let (i, is_result) = self.bodies
+            .iter()
+            .enumerate()
+            .find_map(|(i, b)| {
+                let method = b.target_uri
+                    .strip_prefix(response_uri)?
+                    .strip_prefix('/')?;
+                match method {
+                    "onResult" => Some((i, true)),
+                    "onStatus" => Some((i, false)),
+                    _ => None,
+                }
+            })?;
+        let data = self.bodies.remove(i).data;
+        Some(if is_result {
+            Response::Result(data)
+        } else {
+            Response::Status(data)
+        })
+    }
+
+}
+
+/// The answer of a call in a response packet.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Response {
+    /// the call succeeded, sent to `{response_uri}/onResult`.
+    Result(Value),
+    /// the call failed, sent to `{response_uri}/onStatus`, see [`RemoteFault`].
+    Status(Value),
+}
+
+/// The fault object sent to `onStatus`.
+///
+/// Field names of amfphp 1.x (`code`, `description`, `details`) are accepted as well.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(default)]
+pub struct RemoteFault {
+    #[serde(rename = "faultCode", alias = "code")]
+    pub fault_code: String,
+    #[serde(rename = "faultString", alias = "description")]
+    pub fault_string: String,
+    #[serde(rename = "faultDetail", alias = "details")]
+    pub fault_detail: String,
+}
+
+impl RemoteFault {
+    /// read the fault from the data of an `onStatus` body,
+    /// anything other than an object is kept as `fault_string`.
+    pub fn from_status(data: &Value) -> Self {
+        super::from_value(data).unwrap_or_else(|_| RemoteFault {
+            fault_string: format!("{:?}", data),
+            ..Default::default()
+        })
     }
+}
 
+impl std::fmt::Display for RemoteFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "remote fault `{}`: {}", self.fault_code, self.fault_string)?;
+        if !self.fault_detail.is_empty() {
+            write!(f, " ({})", self.fault_detail)?;
+        }
+        Ok(())
+    }
 }
 
+impl std::error::Error for RemoteFault {}
+
 #[derive(Default)]
-pub struct PacketBuilder<'a> {
+pub struct PacketBuilder {
     version: Option<super::Version>,
-    headers: Vec<Header<'a>>,
-    bodies: Vec<Body<'a>>,
+    headers: Vec<Header>,
+    bodies: Vec<Body>,
 }
 
-impl<'a> PacketBuilder<'a> {
+impl PacketBuilder {
 
     /// return a new packet with `amf3` version
     #[inline]
@@ -91,7 +161,7 @@ impl<'a> PacketBuilder<'a> {
         PacketBuilder::default()
     }
 
-    pub fn build(self) -> Result<Packet<'a>, &'static str> {
+    pub fn build(self) -> Result<Packet, &'static str> {
         Ok(Packet {
             version: self.version.ok_or("the packet version must be set.")?,
             headers: self.headers,
@@ -109,9 +179,9 @@ impl<'a> PacketBuilder<'a> {
         self
     }
 
-    pub fn header(mut self, name: &'a str, must_understand: bool, data: Value) -> Self {
+    pub fn header(mut self, name: impl Into<String>, must_understand: bool, data: Value) -> Self {
         let header = Header {
-            name,
+            name: name.into(),
             must_understand,
             data,
         };
@@ -119,14 +189,14 @@ impl<'a> PacketBuilder<'a> {
         self
     }
 
-    pub fn body<V>(mut self, target_uri: &'a str, response_uri: &'a str, data: V) -> Self
+    pub fn body<V>(mut self, target_uri: impl Into<String>, response_uri: impl Into<String>, data: V) -> Self
     where
         V: Into<Value>
     {
         let data = data.into();
         let body = Body {
-            target_uri,
-            response_uri,
+            target_uri: target_uri.into(),
+            response_uri: response_uri.into(),
             data,
         };
         self.bodies.push(body);
@@ -141,48 +211,61 @@ pub trait IntoBytes {
 
 impl IntoBytes for Value {
     fn into_bytes(self) -> Bytes {
-        let mut bytes = BytesMut::with_capacity(self.size_hint()).writer();
+        let mut bytes = Vec::with_capacity(self.size_hint());
         self.write_to(&mut bytes).unwrap();
 
-        bytes.into_inner().freeze()
+        bytes.into()
     }
 }
 
-impl IntoBytes for Packet<'_> {
+impl IntoBytes for Packet {
     fn into_bytes(self) -> Bytes {
         self.into()
     }
 }
 
-impl Into<Bytes> for Packet<'_> {
-    fn into(self) -> Bytes {
-        let mut bytes = BytesMut::with_capacity(self.size_hint());
-        bytes.put_u16(self.version.val());
+impl From<Packet> for Bytes {
+    fn from(packet: Packet) -> Bytes {
+        // `size_hint` is exact, so the whole packet is written with a single allocation.
+        let mut bytes = Vec::with_capacity(packet.size_hint());
+        bytes.put_u16(packet.version.val());
 
-        bytes.put_u16(self.headers.len() as u16);
+        bytes.put_u16(packet.headers.len() as u16);
 
         // put headers
-        for h in self.headers {
-            let _header_bytes: Bytes = h.into();
-            bytes.put(_header_bytes);
+        for h in &packet.headers {
+            h.write_to(&mut bytes);
         }
 
-        bytes.put_u16(self.bodies.len() as u16);
+        bytes.put_u16(packet.bodies.len() as u16);
 
         // put bodies
-        for b in self.bodies {
-            let _body_bytes: Bytes = b.into();
-            bytes.put(_body_bytes);
+        for b in &packet.bodies {
+            b.write_to(&mut bytes);
         }
 
-        bytes.freeze()
+        bytes.into()
     }
 }
 
-impl Into<Bytes> for Header<'_> {
-    fn into(self) -> Bytes {
-        let mut bytes = BytesMut::new();
+impl From<Header> for Bytes {
+    fn from(header: Header) -> Bytes {
+        let mut bytes = Vec::with_capacity(header.size_hint());
+        header.write_to(&mut bytes);
+        bytes.into()
+    }
+}
+
+impl From<Body> for Bytes {
+    fn from(body: Body) -> Bytes {
+        let mut bytes = Vec::with_capacity(body.size_hint());
+        body.write_to(&mut bytes);
+        bytes.into()
+    }
+}
 
+impl Header {
+    fn write_to(&self, bytes: &mut Vec<u8>) {
         // put name' length before name itself
         bytes.put_u16(self.name.len() as u16);
         bytes.put(self.name.as_bytes());
@@ -190,44 +273,48 @@ impl Into<Bytes> for Header<'_> {
         bytes.put_u8(self.must_understand as u8);
 
         // put data' length before the data itself
-        let _header_data: Bytes = self.data.into_bytes();
-        bytes.put_u32(_header_data.len() as u32);
-        bytes.put(_header_data);
-
-        bytes.freeze()
+        write_data(&self.data, bytes);
     }
 }
 
-impl Into<Bytes> for Body<'_> {
-    fn into(self) -> Bytes {
-        let mut bytes = BytesMut::new();
-
+impl Body {
+    fn write_to(&self, bytes: &mut Vec<u8>) {
         bytes.put_u16(self.target_uri.len() as u16);
         bytes.put(self.target_uri.as_bytes());
 
         bytes.put_u16(self.response_uri.len() as u16);
         bytes.put(self.response_uri.as_bytes());
 
-        let _data: Bytes = self.data.into_bytes();
-        bytes.put_u32(_data.len() as u32);
-        bytes.put(_data);
-
-        bytes.freeze()
+        write_data(&self.data, bytes);
     }
 }
 
-impl SizeHint for Header<'_> {
+/// write the data with a `u32` length prefix, which is filled after the data is written.
+fn write_data(data: &Value, bytes: &mut Vec<u8>) {
+    let len_at = bytes.len();
+    bytes.put_u32(0);
+
+    data.write_to(&mut *bytes).unwrap();
+
+    let len = (bytes.len() - len_at - size_of::<u32>()) as u32;
+    bytes[len_at..len_at + size_of::<u32>()].copy_from_slice(&len.to_be_bytes());
+}
+
+impl SizeHint for Header {
     fn size_hint(&self) -> usize {
         (16 + 8 + 32) / 8 + self.name.len() + self.data.size_hint()
     }
 }
 
-impl SizeHint for Body<'_> {
+impl SizeHint for Body {
     fn size_hint(&self) -> usize {
         (16 + 16 + 32) / 8 + self.target_uri.len() + self.response_uri.len() + self.data.size_hint()
     }
 }
 
+/// The number of bytes a value takes after being encoded.
+///
+/// The `amf` encoder never writes references, so the hints are exact.
 pub(crate) trait SizeHint {
     fn size_hint(&self) -> usize;
 }
@@ -243,68 +330,140 @@ impl SizeHint for Value {
 
 impl SizeHint for amf0::Value {
     fn size_hint(&self) -> usize {
-        return size_of::<u8>() + 128;
-
-        // use amf::Amf0Value::*;
-        //
-        // fn size_of_str(s: &str) -> usize {
-        //     // string length & string
-        //     size_of::<u16>() + s.len()
-        // }
-        //
+        use amf::Amf0Value::*;
+
+        fn size_of_str(s: &str) -> usize {
+            // string length & string
+            size_of::<u16>() + s.len()
+        }
+
+        fn size_of_long_str(s: &str) -> usize {
+            size_of::<u32>() + s.len()
+        }
+
+        fn size_of_pairs(pairs: &[amf::Pair<std::string::String, amf0::Value>]) -> usize {
+            pairs.iter()
+                .map(|p| size_of_str(&p.key) + p.value.size_hint())
+                .sum::<usize>()
+                // empty key & object end marker
+                + size_of::<u16>() + size_of::<u8>()
+        }
+
         // the first byte indicates AMF type (type marker)
-        // size_of::<u8>() + match self {
-        //     Number(val) => size_of_val(val),
-        //     Boolean(val) => size_of_val(val),
-        //     String(val) => size_of_str(val),
-        //     Object { class_name, entries } =>
-        //             todo!(),
-        //     Null | Undefined=> 0,
-        //     EcmaArray { entries } => todo!(),
-        //     Array { entries } =>
-        //             entries.iter()
-        //                 .map(SizeHint::size_hint)
-        //                 .sum::<usize>(),
-        //     Date { unix_time: _, time_zone: _ } =>
-        //             size_of::<f64>()
-        //             + size_of::<i16>(),
-        //     XmlDocument(val) => val.len(),
-        //     AvmPlus(val) => val.size_hint(),
-        // }
+        size_of::<u8>() + match self {
+            Number(_) => size_of::<f64>(),
+            Boolean(_) => size_of::<u8>(),
+            String(val) if val.len() <= 0xFFFF => size_of_str(val),
+            String(val) => size_of_long_str(val),
+            Object { class_name, entries } =>
+                    class_name.as_deref().map_or(0, size_of_str)
+                    + size_of_pairs(entries),
+            Null | Undefined => 0,
+            EcmaArray { entries } =>
+                    size_of::<u32>()
+                    + size_of_pairs(entries),
+            Array { entries } =>
+                    size_of::<u32>()
+                    + entries.iter()
+                        .map(SizeHint::size_hint)
+                        .sum::<usize>(),
+            Date { unix_time: _, time_zone: _ } =>
+                    size_of::<f64>()
+                    + size_of::<i16>(),
+            XmlDocument(val) => size_of_long_str(val),
+            AvmPlus(val) => val.size_hint(),
+        }
     }
 }
 
 impl SizeHint for amf3::Value {
     fn size_hint(&self) -> usize {
-        return size_of::<u8>() + 128;
-
-        // use amf::Amf3Value::*;
-        //
-        // // the first byte indicates AMF type (type marker)
-        // size_of::<u8>() + match self {
-        //     Undefined | Null => 0,
-        //     Boolean(val) => size_of_val(val),
-        //     Integer(val) => todo!(),
-        //     Double(val) => todo!(),
-        //     String(_) => todo!(),
-        //     XmlDocument(val) => todo!(),
-        //     Date { unix_time } => todo!(),
-        //     Array { assoc_entries, dense_entries } => todo!(),
-        //     Object { class_name, sealed_count, entries } => todo!(),
-        //     Xml(val) => val.len(),
-        //     ByteArray(val) => val.len(),
-        //     IntVector { is_fixed, entries } => todo!(),
-        //     UintVector { is_fixed, entries } => todo!(),
-        //     DoubleVector { is_fixed, entries } => todo!(),
-        //     ObjectVector { class_name, is_fixed, entries } => todo!(),
-        //     Dictionary { is_weak, entries } => todo!(),
-        // }
-    }
-}
-
-impl Into<reqwest::Body> for Packet<'_> {
-    fn into(self) -> reqwest::Body {
-        self.into_bytes().into()
+        use amf::Amf3Value::*;
+
+        fn size_of_u29(u29: u32) -> usize {
+            match u29 {
+                0..=0x7F => 1,
+                0x80..=0x3FFF => 2,
+                0x4000..=0x1F_FFFF => 3,
+                _ => 4,
+            }
+        }
+
+        // sizes and string lengths are written as `u29` with the lowest bit set
+        fn size_of_size(size: usize) -> usize {
+            size_of_u29(((size << 1) | 1) as u32)
+        }
+
+        fn size_of_str(s: &str) -> usize {
+            size_of_size(s.len()) + s.len()
+        }
+
+        fn size_of_pairs(pairs: &[amf::Pair<std::string::String, amf3::Value>]) -> usize {
+            pairs.iter()
+                .map(|p| size_of_str(&p.key) + p.value.size_hint())
+                .sum::<usize>()
+                // empty string as the end of pairs
+                + size_of_str("")
+        }
+
+        // the first byte indicates AMF type (type marker)
+        size_of::<u8>() + match self {
+            Undefined | Null | Boolean(_) => 0,
+            Integer(val) if *val >= 0 => size_of_u29(*val as u32),
+            Integer(val) => size_of_u29(((1 << 29) + val) as u32),
+            Double(_) => size_of::<f64>(),
+            String(val) | XmlDocument(val) | Xml(val) => size_of_str(val),
+            Date { unix_time: _ } => size_of_size(0) + size_of::<f64>(),
+            Array { assoc_entries, dense_entries } =>
+                    size_of_size(dense_entries.len())
+                    + size_of_pairs(assoc_entries)
+                    + dense_entries.iter()
+                        .map(SizeHint::size_hint)
+                        .sum::<usize>(),
+            Object { class_name, sealed_count, entries } => {
+                let (sealed, dynamic) = entries.split_at(*sealed_count);
+                let is_dynamic = !dynamic.is_empty() as usize;
+                // traits: flags, class name and the keys of sealed members
+                size_of_size((sealed_count << 3) | (is_dynamic << 2) | 1)
+                    + size_of_str(class_name.as_deref().unwrap_or(""))
+                    + sealed.iter()
+                        .map(|p| size_of_str(&p.key) + p.value.size_hint())
+                        .sum::<usize>()
+                    + if dynamic.is_empty() { 0 } else { size_of_pairs(dynamic) }
+            },
+            ByteArray(val) => size_of_size(val.len()) + val.len(),
+            IntVector { is_fixed: _, entries } =>
+                    size_of_size(entries.len())
+                    + size_of::<u8>()
+                    + entries.len() * size_of::<i32>(),
+            UintVector { is_fixed: _, entries } =>
+                    size_of_size(entries.len())
+                    + size_of::<u8>()
+                    + entries.len() * size_of::<u32>(),
+            DoubleVector { is_fixed: _, entries } =>
+                    size_of_size(entries.len())
+                    + size_of::<u8>()
+                    + entries.len() * size_of::<f64>(),
+            ObjectVector { class_name, is_fixed: _, entries } =>
+                    size_of_size(entries.len())
+                    + size_of::<u8>()
+                    + size_of_str(class_name.as_deref().unwrap_or("*"))
+                    + entries.iter()
+                        .map(SizeHint::size_hint)
+                        .sum::<usize>(),
+            Dictionary { is_weak: _, entries } =>
+                    size_of_size(entries.len())
+                    + size_of::<u8>()
+                    + entries.iter()
+                        .map(|p| p.key.size_hint() + p.value.size_hint())
+                        .sum::<usize>(),
+        }
+    }
+}
+
+impl From<Packet> for reqwest::Body {
+    fn from(packet: Packet) -> reqwest::Body {
+        packet.into_bytes().into()
     }
 }
 
@@ -313,96 +472,364 @@ pub trait ReadAs<Target> {
     fn read_as(&mut self) -> Result<Target, Self::Error>;
 }
 
-impl<'a, Src: Buf> ReadAs<Packet<'a>> for Src {
-    type Error = Box<dyn std::error::Error>;
+impl<Src: Buf + Clone> ReadAs<Packet> for Src {
+    type Error = PacketError;
+
+    fn read_as(&mut self) -> Result<Packet, Self::Error> {
+        read_with(self, |r| {
+            let ver = r.u16(Field::Version)?;
+            let version = Version::parse(ver)
+                .ok_or_else(|| PacketError::UnknownVersion {
+                    version: ver,
+                    at: r.location_at(r.pos - size_of::<u16>(), Field::Version),
+                })?;
+
+            let header_len = r.u16(Field::HeaderCount)? as usize;
+            let mut headers = Vec::with_capacity(header_len);
+            for i in 0..header_len {
+                r.section = Section::Header(i);
+                headers.push(r.header(version)?);
+            }
+
+            r.section = Section::Preamble;
+            let body_len = r.u16(Field::BodyCount)? as usize;
+            let mut bodies = Vec::with_capacity(body_len);
+            for i in 0..body_len {
+                r.section = Section::Body(i);
+                bodies.push(r.body(version)?);
+            }
+
+            Ok(Packet {
+                version,
+                headers,
+                bodies
+            })
+        })
+    }
+}
 
-    fn read_as(&mut self) -> Result<Packet<'a>, Self::Error> {
-        let ver = self.try_get_u16()?;
-        let version = Version::parse(ver)
-            .ok_or("unknow amf version")?;
+/// read a header outside of a packet, the data is treated as a part of an `amf0` packet.
+impl<Src: Buf + Clone> ReadAs<Header> for Src {
+    type Error = PacketError;
 
-        let header_len = self.try_get_u16()? as usize;
-        let mut headers = Vec::with_capacity(header_len);
-        for _ in 0..header_len {
-            headers.push(self.read_as()
-                .map_err(|e| {
-                    format!("fail to parse amf packet header: {}", e)
-                })?
-            );
-        }
+    fn read_as(&mut self) -> Result<Header, Self::Error> {
+        read_with(self, |r| {
+            r.section = Section::Header(0);
+            r.header(Version::Amf0)
+        })
+    }
+}
 
-        let body_len = self.try_get_u16()? as usize;
-        let mut bodies = Vec::with_capacity(body_len);
-        for _ in 0..body_len {
-            bodies.push(self.read_as()
-                .map_err(|e| {
-                    format!("fail to parse amf packet body: {}", e)
-                })?
-            );
-        }
+/// read a body outside of a packet, the data is treated as a part of an `amf0` packet.
+impl<Src: Buf + Clone> ReadAs<Body> for Src {
+    type Error = PacketError;
 
-        Ok(Packet {
-            version,
-            headers,
-            bodies
+    fn read_as(&mut self) -> Result<Body, Self::Error> {
+        read_with(self, |r| {
+            r.section = Section::Body(0);
+            r.body(Version::Amf0)
         })
     }
 }
 
-impl<'a, Src: Buf> ReadAs<Header<'a>> for Src {
-    type Error = Box<dyn std::error::Error>;
+/// Run `f` on the remaining bytes of `src`, and advance `src` by the bytes consumed if it succeeds.
+///
+/// Offsets in errors are relative to the position of `src` when it is called.
+fn read_with<Src, T, F>(src: &mut Src, f: F) -> Result<T, PacketError>
+where
+    Src: Buf + Clone,
+    F: FnOnce(&mut Reader<'_>) -> Result<T, PacketError>,
+{
+    let (ret, consumed) = if src.chunk().len() == src.remaining() {
+        let mut r = Reader::new(src.chunk());
+        (f(&mut r)?, r.pos)
+    } else {
+        // not contiguous, decode a copy so `src` is untouched on error.
+        let bytes = src.clone().copy_to_bytes(src.remaining());
+        let mut r = Reader::new(&bytes);
+        (f(&mut r)?, r.pos)
+    };
+    src.advance(consumed);
+    Ok(ret)
+}
 
-    fn read_as(&mut self) -> Result<Header<'a>, Self::Error> {
-        let len = self.try_get_u16()?;
-        let name = self.try_copy_to_bytes(len as usize)
-            .map_err(|e| format!("fail to parse name: {}",e))?;
+/// A cursor on the raw bytes of a packet, which keeps track of where it is.
+struct Reader<'a> {
+    src: &'a [u8],
+    pos: usize,
+    section: Section,
+}
 
-        let must_understand = self.try_get_u8()
-            .map_err(|e| format!("fail to parse must_understand: {}",e))?
+impl<'a> Reader<'a> {
+    fn new(src: &'a [u8]) -> Self {
+        Reader {
+            src,
+            pos: 0,
+            section: Section::Preamble,
+        }
+    }
+
+    fn location_at(&self, offset: usize, field: Field) -> Location {
+        Location {
+            offset,
+            section: self.section,
+            field,
+            snippet: hexdump(self.src, offset),
+        }
+    }
+
+    fn take(&mut self, len: usize, field: Field) -> Result<&'a [u8], PacketError> {
+        let remaining = self.src.len() - self.pos;
+        if remaining < len {
+            return Err(PacketError::UnexpectedEof {
+                needed: len,
+                remaining,
+                at: self.location_at(self.pos, field),
+            });
+        }
+        let bytes = &self.src[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self, field: Field) -> Result<u8, PacketError> {
+        Ok(self.take(size_of::<u8>(), field)?[0])
+    }
+
+    fn u16(&mut self, field: Field) -> Result<u16, PacketError> {
+        self.take(size_of::<u16>(), field)
+            .map(|mut b| b.get_u16())
+    }
+
+    fn u32(&mut self, field: Field) -> Result<u32, PacketError> {
+        self.take(size_of::<u32>(), field)
+            .map(|mut b| b.get_u32())
+    }
+
+    /// read a `u16` length-prefixed utf-8 string, as used by header names and body uris.
+    fn utf8(&mut self, field: Field) -> Result<String, PacketError> {
+        let len = self.u16(field)? as usize;
+        let start = self.pos;
+        let bytes = self.take(len, field)?;
+        String::from_utf8(bytes.to_vec())
+            .map_err(|source| PacketError::InvalidUtf8 {
+                source,
+                at: self.location_at(start, field),
+            })
+    }
+
+    /// read the `u32` length-prefixed data of a header or a body.
+    fn data(&mut self, version: Version) -> Result<Value, PacketError> {
+        let len = self.u32(Field::DataLength)? as usize;
+        let start = self.pos;
+        let bytes = self.take(len, Field::Data)?;
+        read_data(bytes, version)
+            .map_err(|source| PacketError::InvalidData {
+                source,
+                at: self.location_at(start, Field::Data),
+            })
+    }
+
+    fn header(&mut self, version: Version) -> Result<Header, PacketError> {
+        let name = self.utf8(Field::Name)?;
+        let must_understand = self.u8(Field::MustUnderstand)?
             .eq(&0)
             .not();
+        let data = self.data(version)?;
 
-        let len = self.try_get_u32()?;
-        let data = self.try_copy_to_bytes(len as usize)?;
-        let data = Value::read_from(data.reader(), Version::Amf0)
-            .map_err(|e| format!("fail to parse data: {}",e))?;
-
-        unsafe {
-            let name = transmute(name.as_ref());
-            Ok(Header {
-                name,
-                must_understand,
-                data,
-            })
-        }
+        Ok(Header {
+            name,
+            must_understand,
+            data,
+        })
+    }
+
+    fn body(&mut self, version: Version) -> Result<Body, PacketError> {
+        let target_uri = self.utf8(Field::TargetUri)?;
+        let response_uri = self.utf8(Field::ResponseUri)?;
+        let data = self.data(version)?;
+
+        Ok(Body {
+            target_uri,
+            response_uri,
+            data,
+        })
     }
 }
 
-impl<'a,Src: Buf> ReadAs<Body<'a>> for Src {
-    type Error = Box<dyn std::error::Error>;
+/// Decode the data of a header or a body.
+///
+/// Data in a packet always starts as `amf0`, and the avmplus marker (`0x11`)
+/// switches the rest of the value to `amf3`, such value is returned as `Value::Amf3`.
+/// Some gateways write raw `amf3` data in `amf3` packets, so for those packets
+/// the data is decoded as `amf3` again unless it is an `amf0` value taking the whole data,
+/// e.g. the `amf3` string marker `0x06` alone is the `amf0` undefined.
+fn read_data(data: &[u8], version: Version) -> amf::DecodeResult<Value> {
+    let mut rest = data;
+    let amf0 = amf0::Value::read_from(&mut rest);
+    match (amf0, version) {
+        (Ok(amf0::Value::AvmPlus(val)), _) => Ok(Value::Amf3(val)),
+        (Ok(val), Version::Amf0) => Ok(Value::Amf0(val)),
+        (Ok(val), Version::Amf3) if rest.is_empty() => Ok(Value::Amf0(val)),
+        (Ok(_), Version::Amf3) => amf3::Value::read_from(data).map(Value::Amf3),
+        (Err(e), Version::Amf0) => Err(e),
+        (Err(e), Version::Amf3) => amf3::Value::read_from(data)
+            .map(Value::Amf3)
+            .map_err(|_| e),
+    }
+}
 
-    fn read_as(&mut self) -> Result<Body<'a>, Self::Error> {
-        let len = self.try_get_u16()?;
-        let target_uri = self.try_copy_to_bytes(len as usize)
-            .map_err(|e| format!("fail to parse target_uri: {}",e))?;
+/// The error returned when a packet can not be decoded.
+#[derive(Debug)]
+pub enum PacketError {
+    /// the packet ends in the middle of a field,
+    /// e.g. the server closed the connection early.
+    UnexpectedEof {
+        needed: usize,
+        remaining: usize,
+        at: Location,
+    },
+    /// the first two bytes are not a known amf version,
+    /// usually it is not an amf packet at all, e.g. an html error page.
+    UnknownVersion {
+        version: u16,
+        at: Location,
+    },
+    /// a header name or a body uri is not valid utf-8.
+    InvalidUtf8 {
+        source: std::string::FromUtf8Error,
+        at: Location,
+    },
+    /// the data of a header or a body is not a valid amf value.
+    InvalidData {
+        source: amf::error::DecodeError,
+        at: Location,
+    },
+}
 
-        let len = self.try_get_u16()?;
-        let response_uri = self.try_copy_to_bytes(len as usize)
-            .map_err(|e| format!("fail to parse response_uri: {}",e))?;
+/// Where a [`PacketError`] happens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    /// offset of the failed field from the start of the packet.
+    pub offset: usize,
+    pub section: Section,
+    pub field: Field,
+    /// hexdump of the bytes around `offset`.
+    pub snippet: String,
+}
 
-        let len = self.try_get_u32()?;
-        let data = self.try_copy_to_bytes(len as usize)?;
-        let data = Value::read_from(data.reader(), Version::Amf0)
-            .map_err(|e| format!("fail to parse data: {}",e))?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    /// the version and the counts of headers and bodies.
+    Preamble,
+    /// the header at the index.
+    Header(usize),
+    /// the body at the index.
+    Body(usize),
+}
 
-        unsafe {
-            let target_uri = transmute(target_uri.as_ref());
-            let response_uri = transmute(response_uri.as_ref());
-            Ok(Body {
-                target_uri,
-                response_uri,
-                data,
-            })
-        }    
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Version,
+    HeaderCount,
+    BodyCount,
+    Name,
+    MustUnderstand,
+    TargetUri,
+    ResponseUri,
+    DataLength,
+    Data,
+}
+
+impl PacketError {
+    pub fn location(&self) -> &Location {
+        use PacketError::*;
+        match self {
+            UnexpectedEof { at, .. }
+            | UnknownVersion { at, .. }
+            | InvalidUtf8 { at, .. }
+            | InvalidData { at, .. } => at,
+        }
+    }
+}
+
+impl std::fmt::Display for PacketError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use PacketError::*;
+        match self {
+            UnexpectedEof { needed, remaining, .. } =>
+                write!(f, "unexpected end of packet, {} bytes needed but only {} left", needed, remaining)?,
+            UnknownVersion { version, .. } =>
+                write!(f, "unknown amf version {:#06x}, it may not be an amf packet", version)?,
+            InvalidUtf8 { source, .. } =>
+                write!(f, "invalid utf-8: {}", source)?,
+            InvalidData { source, .. } =>
+                write!(f, "invalid amf data: {}", source)?,
+        }
+        let at = self.location();
+        write!(f, " (at byte {}, {} {:?})\n{}", at.offset, at.section, at.field, at.snippet)
+    }
+}
+
+impl std::error::Error for PacketError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use PacketError::*;
+        match self {
+            InvalidUtf8 { source, .. } => Some(source),
+            InvalidData { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Section {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Section::Preamble => f.write_str("preamble"),
+            Section::Header(i) => write!(f, "header #{}", i),
+            Section::Body(i) => write!(f, "body #{}", i),
+        }
+    }
+}
+
+/// Dump 2 lines of 16 bytes around `offset` like `xxd`, the byte at `offset` is wrapped in `[]`.
+fn hexdump(src: &[u8], offset: usize) -> String {
+    use std::fmt::Write;
+
+    const WIDTH: usize = 16;
+    let start = offset.saturating_sub(WIDTH) / WIDTH * WIDTH;
+    let end = (start + WIDTH * 2).min(src.len());
+
+    let mut dump = String::new();
+    for (i, line) in src[start.min(end)..end].chunks(WIDTH).enumerate() {
+        let line_start = start + i * WIDTH;
+        write!(dump, "{:08x}:", line_start).unwrap();
+        for (j, b) in line.iter().enumerate() {
+            if line_start + j == offset {
+                write!(dump, "[{:02x}", b).unwrap();
+            } else if j > 0 && line_start + j == offset + 1 {
+                write!(dump, "]{:02x}", b).unwrap();
+            } else {
+                write!(dump, " {:02x}", b).unwrap();
+            }
+        }
+        if line_start + line.len() == offset + 1 {
+            dump.push(']');
+        } else {
+            dump.push(' ');
+        }
+        for _ in line.len()..WIDTH {
+            dump.push_str("   ");
+        }
+        dump.push(' ');
+        dump.extend(line.iter().map(|&b| match b {
+            0x20..=0x7e => b as char,
+            _ => '.',
+        }));
+        dump.push('\n');
+    }
+    if offset >= src.len() {
+        dump.push_str("<end of packet>");
     }
+    dump.trim_end().to_owned()
 }
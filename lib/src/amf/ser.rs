@@ -0,0 +1,321 @@
+//! serialize rust values into `amf0` values, which is what the game client sends.
+//!
+//! - tuples and sequences become strict arrays, so the arguments of a call
+//!   can be written as a tuple: `(plant_id, skill_id)`
+//! - structs and maps become anonymous objects
+//! - all numbers become `Number`
+//! - `None` and `()` become `Null`
+
+use amf::{Amf0Value, Pair};
+use serde::ser::{self, Serialize};
+
+use super::de::Error;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// serialize `value` as an `amf0` value.
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<Amf0Value> {
+    value.serialize(Serializer)
+}
+
+pub struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = Amf0Value;
+    type Error = Error;
+
+    type SerializeSeq = SerializeArray;
+    type SerializeTuple = SerializeArray;
+    type SerializeTupleStruct = SerializeArray;
+    type SerializeTupleVariant = SerializeVariant<SerializeArray>;
+    type SerializeMap = SerializeObject;
+    type SerializeStruct = SerializeObject;
+    type SerializeStructVariant = SerializeVariant<SerializeObject>;
+
+    fn serialize_bool(self, v: bool) -> Result<Amf0Value> {
+        Ok(Amf0Value::Boolean(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Amf0Value> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Amf0Value> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Amf0Value> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Amf0Value> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Amf0Value> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Amf0Value> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Amf0Value> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Amf0Value> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Amf0Value> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Amf0Value> {
+        Ok(Amf0Value::Number(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Amf0Value> {
+        Ok(Amf0Value::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Amf0Value> {
+        Ok(Amf0Value::String(v.to_owned()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Amf0Value> {
+        let entries = v.iter()
+            .map(|b| Amf0Value::Number(*b as f64))
+            .collect();
+        Ok(Amf0Value::Array { entries })
+    }
+
+    fn serialize_none(self) -> Result<Amf0Value> {
+        Ok(Amf0Value::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Amf0Value> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Amf0Value> {
+        Ok(Amf0Value::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Amf0Value> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Amf0Value> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Amf0Value> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Amf0Value> {
+        Ok(single_entry_object(variant, to_value(value)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeArray> {
+        Ok(SerializeArray {
+            entries: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeArray> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SerializeArray> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeArray>> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_seq(Some(len))?,
+        })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<SerializeObject> {
+        Ok(SerializeObject {
+            entries: Vec::with_capacity(len.unwrap_or(0)),
+            next_key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeObject> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeObject>> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_map(Some(len))?,
+        })
+    }
+}
+
+fn single_entry_object(key: &str, value: Amf0Value) -> Amf0Value {
+    Amf0Value::Object {
+        class_name: None,
+        entries: vec![Pair { key: key.to_owned(), value }],
+    }
+}
+
+pub struct SerializeArray {
+    entries: Vec<Amf0Value>,
+}
+
+impl ser::SerializeSeq for SerializeArray {
+    type Ok = Amf0Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.entries.push(to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Amf0Value> {
+        Ok(Amf0Value::Array { entries: self.entries })
+    }
+}
+
+impl ser::SerializeTuple for SerializeArray {
+    type Ok = Amf0Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Amf0Value> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeArray {
+    type Ok = Amf0Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Amf0Value> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+pub struct SerializeObject {
+    entries: Vec<Pair<String, Amf0Value>>,
+    next_key: Option<String>,
+}
+
+impl ser::SerializeMap for SerializeObject {
+    type Ok = Amf0Value;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        let key = match to_value(key)? {
+            Amf0Value::String(key) => key,
+            Amf0Value::Number(key) => key.to_string(),
+            _ => return Err(Error::from("the key of an object must be a string or a number")),
+        };
+        self.next_key = Some(key);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let key = self.next_key
+            .take()
+            .ok_or("`serialize_value` is called before `serialize_key`")?;
+        self.entries.push(Pair { key, value: to_value(value)? });
+        Ok(())
+    }
+
+    fn end(self) -> Result<Amf0Value> {
+        Ok(Amf0Value::Object {
+            class_name: None,
+            entries: self.entries,
+        })
+    }
+}
+
+impl ser::SerializeStruct for SerializeObject {
+    type Ok = Amf0Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        self.entries.push(Pair { key: key.to_owned(), value: to_value(value)? });
+        Ok(())
+    }
+
+    fn end(self) -> Result<Amf0Value> {
+        ser::SerializeMap::end(self)
+    }
+}
+
+/// `{ variant: inner }`
+pub struct SerializeVariant<S> {
+    variant: &'static str,
+    inner: S,
+}
+
+impl ser::SerializeTupleVariant for SerializeVariant<SerializeArray> {
+    type Ok = Amf0Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        ser::SerializeSeq::serialize_element(&mut self.inner, value)
+    }
+
+    fn end(self) -> Result<Amf0Value> {
+        let value = ser::SerializeSeq::end(self.inner)?;
+        Ok(single_entry_object(self.variant, value))
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeVariant<SerializeObject> {
+    type Ok = Amf0Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        ser::SerializeStruct::serialize_field(&mut self.inner, key, value)
+    }
+
+    fn end(self) -> Result<Amf0Value> {
+        let value = ser::SerializeStruct::end(self.inner)?;
+        Ok(single_entry_object(self.variant, value))
+    }
+}
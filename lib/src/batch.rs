@@ -0,0 +1,155 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    amf::{self, packet::{Packet, Response}, Amf0Value, Value},
+    decode_response, response_data, Client, ErrorKind, Result,
+};
+
+/// Several calls sent in a single packet, so a single http round trip.
+///
+/// The `i`th call (starting from 0) is sent with response uri `/{i + 1}`,
+/// and the results are returned in call order.
+///
+/// ```no_run
+/// # async fn f(client: &pvzol_tools_lib::Client) -> pvzol_tools_lib::Result<()> {
+/// let results = client.batch()
+///     .call("api.duty.reward", (1, 3))
+///     .call("api.duty.reward", (2, 3))
+///     .send()
+///     .await?;
+/// # Ok(()) }
+/// ```
+pub struct Batch<'c> {
+    client: &'c Client,
+    calls: Vec<(String, Result<Amf0Value, amf::Error>)>,
+}
+
+impl Client {
+    /// start a batch of calls, see [`Batch`].
+    pub fn batch(&self) -> Batch<'_> {
+        Batch {
+            client: self,
+            calls: Vec::new(),
+        }
+    }
+}
+
+impl<'c> Batch<'c> {
+    /// queue a call of `target_uri` with `args`.
+    ///
+    /// if `args` can not be serialized, the call is not sent and its result is the error.
+    pub fn call<A: Serialize>(mut self, target_uri: impl Into<String>, args: A) -> Self {
+        self.calls.push((target_uri.into(), amf::to_value(&args)));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.calls.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    /// send all the calls, and return the results in call order.
+    ///
+    /// The outer error means the whole request failed, e.g. a network error.
+    pub async fn send(self) -> Result<Vec<Result<Value>>> {
+        self.send_with(response_data).await
+    }
+
+    /// like [`Batch::send`], but deserialize every result into `R`.
+    pub async fn send_as<R: DeserializeOwned>(self) -> Result<Vec<Result<R>>> {
+        self.send_with(decode_response).await
+    }
+
+    async fn send_with<R, F>(self, decode: F) -> Result<Vec<Result<R>>>
+    where
+        F: Fn(Option<Response>, &str) -> Result<R>,
+    {
+        let n = self.calls.len();
+        let mut builder = Packet::builder().with_default_version();
+        let mut results: Vec<Option<Result<R>>> = Vec::with_capacity(n);
+        for (i, (target_uri, args)) in self.calls.into_iter().enumerate() {
+            match args {
+                Ok(args) => {
+                    builder = builder.body(target_uri, response_uri(i), args);
+                    results.push(None);
+                },
+                Err(e) => results.push(Some(Err(ErrorKind::protocol("fail to serialize arguments", e)))),
+            }
+        }
+
+        let req_packet = builder
+            .build()
+            .map_err(|e| ErrorKind::protocol(format!("fail to build packet: {}", e), None))?;
+        let resp = if req_packet.bodies.is_empty() {
+            req_packet
+        } else {
+            self.client.send_packet(req_packet).await?
+        };
+
+        Ok(split_responses(resp, results, decode))
+    }
+}
+
+#[inline]
+fn response_uri(i: usize) -> String {
+    format!("/{}", i + 1)
+}
+
+/// fill the results which are still `None` with the bodies of `resp`, matched by the response uri.
+fn split_responses<R, F>(
+    mut resp: Packet,
+    results: Vec<Option<Result<R>>>,
+    decode: F,
+) -> Vec<Result<R>>
+where
+    F: Fn(Option<Response>, &str) -> Result<R>,
+{
+    results
+        .into_iter()
+        .enumerate()
+        .map(|(i, res)| res.unwrap_or_else(|| {
+            let uri = response_uri(i);
+            decode(resp.take_response(&uri), &uri)
+        }))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amf::{amf0::{number, object, string}, Version};
+
+    #[test]
+    fn test_split_responses() {
+        // the server answers in any order
+        let fault = object(vec![
+            ("faultCode", string("1")),
+            ("faultString", string("今日已领取")),
+        ].into_iter());
+        let resp = Packet::builder()
+            .version(Version::Amf0)
+            .body("/4/onResult", "null", number(4.))
+            .body("/2/onStatus", "null", fault)
+            .body("/1/onResult", "null", number(1.))
+            .build()
+            .unwrap();
+
+        let results: Vec<Result<f64>> = split_responses(resp, vec![
+            None,
+            None,
+            Some(Err(ErrorKind::protocol("fail to serialize arguments", None))),
+            None,
+            None,
+        ], decode_response);
+
+        assert_eq!(results.len(), 5);
+        assert!(matches!(results[0], Ok(v) if v == 1.));
+        assert!(matches!(&results[1], Err(ErrorKind::Fault(f)) if f.fault_string == "今日已领取"));
+        assert!(matches!(&results[2], Err(ErrorKind::Protocol { .. })));
+        assert!(matches!(results[3], Ok(v) if v == 4.));
+        assert!(matches!(&results[4], Err(ErrorKind::Protocol { message, .. }) if message.contains("`/5`")));
+    }
+}
@@ -0,0 +1,89 @@
+//! Stop the long-running loops of a `Client` from outside, e.g. on Ctrl-C.
+//!
+//! Each loop (e.g. [`Client::quality_up_to`](crate::Client::quality_up_to)) takes a token, which
+//! stops only that loop, so a new token runs the next loop on the same client. The token of the
+//! client ([`ClientBuilder::cancellation`](crate::ClientBuilder::cancellation)) stops all of them.
+//!
+//! The loops check the tokens before each request, so the request in flight is finished
+//! and the loop returns `Ok` as if it is done, after emitting [`Progress::Cancelled`](crate::event::Progress::Cancelled).
+
+use std::{
+    future::Future,
+    sync::{atomic::{AtomicBool, Ordering}, Arc},
+    task::Poll,
+};
+
+use tokio::sync::Notify;
+
+/// A cheaply cloneable flag, all the clones are cancelled together.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// wait until the token is cancelled.
+    pub async fn cancelled(&self) {
+        loop {
+            let notified = self.inner.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// wait until `a` or `b` is cancelled.
+pub(crate) async fn either_cancelled(a: &CancellationToken, b: &CancellationToken) {
+    let mut a = Box::pin(a.cancelled());
+    let mut b = Box::pin(b.cancelled());
+    std::future::poll_fn(|cx| match (a.as_mut().poll(cx), b.as_mut().poll(cx)) {
+        (Poll::Pending, Poll::Pending) => Poll::Pending,
+        _ => Poll::Ready(()),
+    }).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let token = CancellationToken::new();
+        let waiter = tokio::spawn({
+            let token = token.clone();
+            async move { token.cancelled().await }
+        });
+        assert!(!token.is_cancelled());
+
+        token.clone().cancel();
+        waiter.await.unwrap();
+        assert!(token.is_cancelled());
+        // returns at once when it is cancelled already
+        token.cancelled().await;
+
+        let other = CancellationToken::new();
+        either_cancelled(&other, &token).await;
+        assert!(!other.is_cancelled());
+    }
+}
@@ -0,0 +1,121 @@
+//! The cookies of an account, which are sent with every request
+//! and updated by the `Set-Cookie` headers of the responses.
+
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CookieJar {
+    entries: BTreeMap<String, String>,
+}
+
+impl CookieJar {
+    pub fn new() -> Self {
+        CookieJar::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries.get(name).map(String::as_str)
+    }
+
+    /// add or update the cookie, return the old value.
+    pub fn set(&mut self, name: impl Into<String>, value: impl Into<String>) -> Option<String> {
+        self.entries.insert(name.into(), value.into())
+    }
+
+    /// remove the cookie, return the old value.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        self.entries.remove(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// the value of the `Cookie` request header, `name=value; name2=value2`.
+    pub fn header_value(&self) -> String {
+        self.iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+
+    /// apply a `Set-Cookie` response header, return whether the jar is changed.
+    ///
+    /// A cookie is removed if it has `Max-Age` <= 0, or it is set to `deleted` like php does.
+    /// Other attributes (`Path`, `Domain`, `Expires`, ...) are ignored,
+    /// since all requests go to the same gateway.
+    pub fn apply_set_cookie(&mut self, set_cookie: &str) -> bool {
+        let mut parts = set_cookie.split(';');
+        let (name, value) = match parts.next().and_then(|pair| pair.split_once('=')) {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => return false,
+        };
+        if name.is_empty() {
+            return false;
+        }
+
+        let expired = value == "deleted" || parts.any(|attr| {
+            matches!(attr.split_once('='), Some((k, v))
+                if k.trim().eq_ignore_ascii_case("max-age")
+                && v.trim().parse::<i64>().is_ok_and(|age| age <= 0))
+        });
+
+        if expired {
+            self.remove(name).is_some()
+        } else {
+            self.set(name, value).as_deref() != Some(value)
+        }
+    }
+}
+
+impl FromIterator<(String, String)> for CookieJar {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(iter: I) -> Self {
+        CookieJar {
+            entries: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl From<CookieJar> for HashMap<String, String> {
+    fn from(jar: CookieJar) -> Self {
+        jar.entries.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_set_cookie() {
+        let mut jar: CookieJar = [
+            ("PHPSESSID".to_owned(), "old".to_owned()),
+            ("pvz_youkia".to_owned(), "a=b".to_owned()),
+        ].into_iter().collect();
+        assert_eq!(jar.header_value(), "PHPSESSID=old; pvz_youkia=a=b");
+
+        assert!(jar.apply_set_cookie("PHPSESSID=new; path=/; HttpOnly"));
+        assert!(!jar.apply_set_cookie("PHPSESSID=new; path=/"));
+        assert_eq!(jar.get("PHPSESSID"), Some("new"));
+
+        assert!(jar.apply_set_cookie("lang=zh"));
+        assert_eq!(jar.len(), 3);
+
+        assert!(jar.apply_set_cookie("lang=deleted; expires=Thu, 01-Jan-1970 00:00:01 GMT"));
+        assert!(jar.apply_set_cookie("pvz_youkia=x; Max-Age=0"));
+        assert!(!jar.apply_set_cookie("missing=x; max-age=-1"));
+        assert!(!jar.apply_set_cookie("malformed"));
+        assert_eq!(jar.header_value(), "PHPSESSID=new");
+
+        assert_eq!(jar.remove("PHPSESSID").as_deref(), Some("new"));
+        assert!(jar.is_empty());
+    }
+}
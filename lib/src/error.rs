@@ -0,0 +1,220 @@
+//! The error of everything in this crate, match on it to react to specific failures,
+//! e.g. stop a loop when the game rejects the call.
+
+use std::{error::Error, fmt};
+
+use reqwest::StatusCode;
+
+use crate::amf::{self, packet::{PacketError, RemoteFault}};
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// the connection failed before the request is sent, e.g. refused, so it is safe to send again.
+    Connect(Box<dyn Error + Send + Sync>),
+    /// the http round trip failed after the request is sent, e.g. timeout or connection reset.
+    Network(Box<dyn Error + Send + Sync>),
+    /// the gateway responded with a non-success http status.
+    HttpStatus(StatusCode),
+    /// the gateway responded with an empty body.
+    EmptyResponse,
+    /// the response is not a valid amf packet.
+    Packet(PacketError),
+    /// the packet is valid, but not what the call expects,
+    /// e.g. the response of the call is missing or can not be deserialized.
+    Protocol {
+        message: String,
+        source: Option<amf::Error>,
+    },
+    /// the server answered the call with `onStatus`.
+    Fault(RemoteFault),
+    /// the game refused to do it, e.g. not enough items, `description` is the message of the server.
+    GameRejected {
+        reason: Rejection,
+        description: String,
+        code: Option<String>,
+    },
+    /// the session of the account is expired, the cookies need to be refreshed.
+    SessionExpired(String),
+    /// stopped by the [`CancellationToken`](crate::cancel::CancellationToken) of the client.
+    Cancelled,
+    /// the game data (`sys::SysInfo`) is not loaded yet.
+    NotInitialized,
+    /// the id is not in the game data, `kind` is e.g. `organism` or `tool`.
+    NotFound {
+        kind: &'static str,
+        id: usize,
+    },
+    /// the account, the cassette or the client options are invalid.
+    Config(String),
+    /// the cassette or the cache of the game data can not be serialized.
+    Serialize(Box<dyn Error + Send + Sync>),
+    /// the transport or the tool can not do it (yet), e.g. a replay transport fetching a page.
+    Unsupported(String),
+    Io(std::io::Error),
+}
+
+/// Why the game rejects a call, classified from the message of the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Rejection {
+    /// e.g. 品质刷新书不足
+    NotEnoughItems,
+    /// e.g. 金币不足
+    NotEnoughMoney,
+    /// the daily times are used up, or the reward is claimed already.
+    LimitReached,
+    /// the plant or skill can not be upgraded any more.
+    MaxLevel,
+    /// the plant, tool or duty does not exist.
+    NotFound,
+    /// the calls are too frequent, try again later.
+    TooFrequent,
+    Unknown,
+}
+
+impl Rejection {
+    /// keywords of each reason, checked in order.
+    const CATALOG: [(Rejection, &'static [&'static str]); 6] = [
+        (Rejection::TooFrequent, &["频繁", "太快", "稍后再试", "too frequent", "too fast", "rate limit"]),
+        (Rejection::NotEnoughMoney, &["金币不足", "金币不够", "金钱不足", "钻石不足", "not enough money"]),
+        (Rejection::NotEnoughItems, &["书不足", "道具不足", "物品不足", "材料不足", "数量不足", "数量不够", "not enough items"]),
+        (Rejection::LimitReached, &["已用完", "上限", "已领取", "已经领取", "daily limit", "limit reached"]),
+        (Rejection::MaxLevel, &["已满级", "已达到最高", "已达最高", "已是最高", "已经是最高", "max level", "maximum level"]),
+        (Rejection::NotFound, &["不存在", "找不到", "not found", "not exist"]),
+    ];
+
+    pub fn classify(description: &str) -> Rejection {
+        let description = description.to_lowercase();
+        Rejection::CATALOG
+            .iter()
+            .find(|(_, keywords)| keywords.iter().any(|k| description.contains(k)))
+            .map_or(Rejection::Unknown, |(reason, _)| *reason)
+    }
+
+    /// nothing more can be done by calling again, e.g. the items are used up.
+    pub fn is_exhausted(&self) -> bool {
+        matches!(
+            self,
+            Rejection::NotEnoughItems | Rejection::NotEnoughMoney | Rejection::LimitReached | Rejection::MaxLevel
+        )
+    }
+}
+
+impl ErrorKind {
+    /// the game rejection reason, also for faults which are known rejections.
+    pub fn rejection(&self) -> Option<Rejection> {
+        match self {
+            ErrorKind::GameRejected { reason, .. } => Some(*reason),
+            ErrorKind::Fault(fault) => match Rejection::classify(&fault.fault_string) {
+                Rejection::Unknown => None,
+                reason => Some(reason),
+            },
+            _ => None,
+        }
+    }
+
+    pub(crate) fn rejected(description: String, code: Option<String>) -> Self {
+        ErrorKind::GameRejected {
+            reason: Rejection::classify(&description),
+            description,
+            code,
+        }
+    }
+
+    pub(crate) fn protocol(message: impl Into<String>, source: impl Into<Option<amf::Error>>) -> Self {
+        ErrorKind::Protocol {
+            message: message.into(),
+            source: source.into(),
+        }
+    }
+}
+
+impl From<PacketError> for ErrorKind {
+    fn from(e: PacketError) -> Self {
+        Self::Packet(e)
+    }
+}
+
+impl From<RemoteFault> for ErrorKind {
+    fn from(e: RemoteFault) -> Self {
+        Self::Fault(e)
+    }
+}
+
+impl From<std::io::Error> for ErrorKind {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ErrorKind::*;
+        match self {
+            Connect(e) => write!(f, "fail to connect: {}", e),
+            Network(e) => write!(f, "network error: {}", e),
+            HttpStatus(status) => write!(f, "amf gateway responded with http status {}", status),
+            EmptyResponse => f.write_str("amf gateway responded with an empty body"),
+            Packet(e) => write!(f, "fail to parse response as AMF packet: {}", e),
+            Protocol { message, source: Some(e) } => write!(f, "{}: {}", message, e),
+            Protocol { message, source: None } => f.write_str(message),
+            Fault(e) => e.fmt(f),
+            GameRejected { description, .. } => f.write_str(description),
+            SessionExpired(reason) => write!(f, "登录已失效, 请更新cookie ({})", reason),
+            Cancelled => f.write_str("已取消"),
+            NotInitialized => f.write_str("游戏数据尚未加载"),
+            NotFound { kind, id } => write!(f, "unknown {} `{}` in the game data", kind, id),
+            Config(e) => write!(f, "配置有误: {}", e),
+            Serialize(e) => write!(f, "fail to serialize: {}", e),
+            Unsupported(e) => write!(f, "不支持: {}", e),
+            Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for ErrorKind {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ErrorKind::Connect(e) | ErrorKind::Network(e) | ErrorKind::Serialize(e) => Some(&**e),
+            ErrorKind::Packet(e) => Some(e),
+            ErrorKind::Protocol { source: Some(e), .. } => Some(e),
+            ErrorKind::Fault(e) => Some(e),
+            ErrorKind::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(Rejection::classify("品质刷新书不足"), Rejection::NotEnoughItems);
+        assert_eq!(Rejection::classify("金币不足"), Rejection::NotEnoughMoney);
+        assert_eq!(Rejection::classify("今日已领取"), Rejection::LimitReached);
+        assert_eq!(Rejection::classify("该植物不存在"), Rejection::NotFound);
+        assert_eq!(Rejection::classify("Operation too frequent"), Rejection::TooFrequent);
+        assert_eq!(Rejection::classify("Rate limit exceeded"), Rejection::TooFrequent);
+        assert_eq!(Rejection::classify("Daily limit reached"), Rejection::LimitReached);
+        assert_eq!(Rejection::classify("speed limit"), Rejection::Unknown);
+        assert_eq!(Rejection::classify("技能已达到最高等级"), Rejection::MaxLevel);
+        assert_eq!(Rejection::classify("已经是最高品质"), Rejection::MaxLevel);
+        assert_eq!(Rejection::classify("超过最高次数"), Rejection::Unknown);
+        assert_eq!(Rejection::classify("道具数量不足"), Rejection::NotEnoughItems);
+        assert_eq!(Rejection::classify("等级不足, 无法挑战"), Rejection::Unknown);
+        assert_eq!(Rejection::classify("Not enough players"), Rejection::Unknown);
+        assert_eq!(Rejection::classify("???"), Rejection::Unknown);
+        assert!(Rejection::LimitReached.is_exhausted());
+        assert!(!Rejection::TooFrequent.is_exhausted());
+
+        let fault = RemoteFault {
+            fault_string: "任务不存在".to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(ErrorKind::Fault(fault).rejection(), Some(Rejection::NotFound));
+        assert_eq!(ErrorKind::Fault(Default::default()).rejection(), None);
+    }
+}
@@ -0,0 +1,87 @@
+//! Things happening inside a `Client`, reported to the handler set by `ClientBuilder::on_event`
+//! and to the receivers of `Client::subscribe`.
+
+use std::{sync::Arc, time::Duration};
+
+use crate::{game::sys::Quality, Rejection};
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Event {
+    /// a request failed for a transient reason, and will be sent again after `delay`.
+    Retry {
+        /// the failed attempt, starting from 1.
+        attempt: u32,
+        delay: Duration,
+        error: String,
+    },
+    /// the session is expired, and the re-auth callback is invoked.
+    Reauth {
+        reason: String,
+    },
+    /// a step of a long-running loop is done.
+    Progress(Progress),
+}
+
+/// The steps of the loops of `Client`, e.g. `quality_up_to`.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Progress {
+    /// the `attempt`th refresh of the quality, `old` is `None` for the first one.
+    QualityUp {
+        plant_id: f64,
+        attempt: usize,
+        old: Option<Quality>,
+        new: Quality,
+    },
+    /// the `attempt`th try to upgrade the skill, `old != new` if it is upgraded.
+    SkillUp {
+        plant_id: f64,
+        attempt: usize,
+        old: f64,
+        new: f64,
+    },
+    /// `amount` boxes are opened in the `round`th round.
+    BoxOpened {
+        box_id: f64,
+        round: usize,
+        amount: u32,
+    },
+    /// the reward of a duty is claimed, or `error` if it fails.
+    DutyReward {
+        duty_id: f64,
+        category_id: f64,
+        error: Option<String>,
+    },
+    /// the medals of the fuben before it is reset.
+    FubenMedals {
+        fuben_id: f64,
+        medals: usize,
+    },
+    /// the reward of the fuben is reset, at the start of the `round`th round.
+    FubenReset {
+        fuben_id: f64,
+        round: usize,
+    },
+    /// the `attempt`th award of the round is got, the round is `done` if there are no more awards.
+    FubenAward {
+        fuben_id: f64,
+        round: usize,
+        attempt: usize,
+        done: bool,
+    },
+    Challenged {
+        fuben_id: f64,
+        round: usize,
+        win: bool,
+    },
+    /// the loop stops early, since the game rejects the call, e.g. the items are used up.
+    Stopped {
+        reason: Rejection,
+        message: String,
+    },
+    /// the loop stops early, since the cancellation token of the client is cancelled.
+    Cancelled,
+}
+
+pub(crate) type EventHandler = Arc<dyn Fn(&Event) + Send + Sync>;
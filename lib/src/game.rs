@@ -1,4 +1,4 @@
-use std::error::Error;
+use crate::ErrorKind;
 
 pub type Id = usize;
 
@@ -7,19 +7,41 @@ pub type Grade = u32;
 pub mod sys;
 pub mod user;
 
-pub(super) type Result<T> = std::result::Result<T, ErrorKind>;
+pub(super) type Result<T> = crate::Result<T>;
 
-pub enum ErrorKind {
-    DataNotInitialized,
-    Other(Box<dyn Error>),
+/// The profile of the player, see [`GetUserInfo::get_user`](user::GetUserInfo::get_user).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GameUser {
+    pub id: Id,
+    /// 昵称
+    pub name: String,
+    /// 等级
+    pub grade: Grade,
+    pub exp: u64,
+    /// 金币
+    pub money: u64,
+    /// 钻石
+    pub diamonds: u64,
+    pub vip_grade: u32,
+    /// 荣誉
+    pub honor: u64,
+    /// the challenges remaining today.
+    pub challenges: ChallengeCounts,
 }
 
-impl ErrorKind {
-    pub(crate) fn other_str(string: &str) -> Self {
-        ErrorKind::Other(string.into())
-    }
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChallengeCounts {
+    /// 副本
+    pub fuben: u32,
+    /// 宝石副本
+    pub stone: u32,
 }
 
-pub struct GameUser {
-    
+impl GameUser {
+    pub fn remaining_challenges(&self, challenge_type: &sys::ChallengeType) -> u32 {
+        match challenge_type {
+            sys::ChallengeType::Fuben => self.challenges.fuben,
+            sys::ChallengeType::Stone => self.challenges.stone,
+        }
+    }
 }
@@ -1,45 +1,87 @@
 
-use std::{str::FromStr};
+use std::{collections::HashMap, str::FromStr, sync::{Arc, RwLock}};
 
-use once_cell::sync::OnceCell;
-use serde::Deserialize;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
 
-use crate::game::*;
+use crate::{game::*, transport::BoxFuture, Client};
 
+mod cache;
+pub(super) mod xml;
+
+pub use cache::SysCache;
+
+/// The game data of a server, indexed by id and name.
+///
+/// It is shared as an `Arc<SysInfo>`, see [`sys_info`] and [`Client::init_sys_info`].
+#[derive(Debug, Default)]
 pub struct SysInfo {
-    pub(crate) organisms: Vec<Organism>,
-    pub(crate) tools: Vec<Tool>,
+    organisms: Vec<Organism>,
+    tools: Vec<Tool>,
+    organism_ids: HashMap<Id, usize>,
+    tool_ids: HashMap<Id, usize>,
+    organism_names: HashMap<String, usize>,
+    tool_names: HashMap<String, usize>,
 }
 
-pub(crate) static SYS_INFO: OnceCell<SysInfo> = OnceCell::new();
+/// the loaded game data, replaced as a whole by [`Client::reload_sys_info`].
+static SYS_INFO: Lazy<RwLock<Option<Arc<SysInfo>>>> = Lazy::new(Default::default);
 
-pub fn get_sys_organisms() -> Result<&'static[Organism]> {
-    if let Some(sys_info) = SYS_INFO.get() {
-        let organisms = sys_info.organisms.as_slice();
-        unsafe {
-            // "almost safe" operation, because sys_info's lifetime is almost equal to the whole app.
-            return Ok(&*(organisms as *const [Organism]));
-        }
-    }
-    Err(ErrorKind::DataNotInitialized)
+/// serialize the downloads of [`Client::init_sys_info`], so the data is downloaded once.
+static SYS_INFO_LOADING: Lazy<tokio::sync::Mutex<()>> = Lazy::new(Default::default);
+
+/// the game data loaded by [`Client::init_sys_info`].
+pub fn sys_info() -> Result<Arc<SysInfo>> {
+    SYS_INFO.read().unwrap().clone().ok_or(ErrorKind::NotInitialized)
 }
 
-pub async fn get_sys_tools() -> Result<&'static[Tool]> {
-    if let Some(sys_info) = SYS_INFO.get() {
-        let tools = sys_info.tools.as_slice();
-        unsafe {
-            // "almost safe" operation, because sys_info's lifetime is almost equal to the whole app.
-            return Ok(&*(tools as *const [Tool]));
+impl SysInfo {
+    /// if several items have the same name, the one with the smallest id is found by the name.
+    pub fn new(mut organisms: Vec<Organism>, mut tools: Vec<Tool>) -> Self {
+        organisms.sort_by_key(|o| o.id);
+        tools.sort_by_key(|t| t.tool_id);
+        let mut sys_info = SysInfo::default();
+        for (i, organism) in organisms.iter().enumerate() {
+            sys_info.organism_ids.insert(organism.id, i);
+            sys_info.organism_names.entry(organism.name.clone()).or_insert(i);
+        }
+        for (i, tool) in tools.iter().enumerate() {
+            sys_info.tool_ids.insert(tool.tool_id, i);
+            sys_info.tool_names.entry(tool.name.clone()).or_insert(i);
         }
+        sys_info.organisms = organisms;
+        sys_info.tools = tools;
+        sys_info
     }
-    Err(ErrorKind::DataNotInitialized)
-}
 
-impl SysInfo {
-    
+    /// all the organisms, sorted by id.
+    pub fn organisms(&self) -> &[Organism] {
+        &self.organisms
+    }
+
+    /// all the tools, sorted by id.
+    pub fn tools(&self) -> &[Tool] {
+        &self.tools
+    }
+
+    pub fn organism(&self, id: Id) -> Option<&Organism> {
+        self.organism_ids.get(&id).map(|&i| &self.organisms[i])
+    }
+
+    pub fn tool(&self, id: Id) -> Option<&Tool> {
+        self.tool_ids.get(&id).map(|&i| &self.tools[i])
+    }
+
+    pub fn organism_by_name(&self, name: &str) -> Option<&Organism> {
+        self.organism_names.get(name).map(|&i| &self.organisms[i])
+    }
+
+    pub fn tool_by_name(&self, name: &str) -> Option<&Tool> {
+        self.tool_names.get(name).map(|&i| &self.tools[i])
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Organism {
     pub id: Id,
     pub name: String,
@@ -59,7 +101,7 @@ pub struct Organism {
     pub evolutions: Vec<Evolution>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Evolution {
     pub id: Id,
     pub grade: Grade,
@@ -68,7 +110,7 @@ pub struct Evolution {
     pub money: usize,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Tool {
     pub tool_id: Id,
     pub name: String,
@@ -101,7 +143,7 @@ pub enum Quality {
 
 impl std::fmt::Display for Quality {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        <Self as std::fmt::Debug>::fmt(&self, f)
+        <Self as std::fmt::Debug>::fmt(self, f)
     }
 }
 
@@ -161,10 +203,57 @@ pub enum QualityUpType {
     Moshen,
 }
 
+/// Download the game data files of the server.
 pub trait GetSysInfo {
     // http://s36.youkia.pvz.youkia.com/pvz/php_xml/tool.xml?1660639233132
-    fn get_tools(&self) -> Vec<Tool>;
+    fn get_tools(&self) -> BoxFuture<'_, Result<Vec<Tool>>>;
 
     // http://s36.youkia.pvz.youkia.com/pvz/php_xml/organism.xml?1660639233148
-    fn get_oganisms(&self) -> Vec<Organism>;
+    fn get_organisms(&self) -> BoxFuture<'_, Result<Vec<Organism>>>;
+}
+
+impl GetSysInfo for Client {
+    fn get_tools(&self) -> BoxFuture<'_, Result<Vec<Tool>>> {
+        Box::pin(async move {
+            match &self.sys_cache {
+                Some(cache) => cache.load(self, "tool.xml", xml::parse_tools).await,
+                None => xml::parse_tools(&self.fetch_xml("tool.xml").await?),
+            }
+        })
+    }
+
+    fn get_organisms(&self) -> BoxFuture<'_, Result<Vec<Organism>>> {
+        Box::pin(async move {
+            match &self.sys_cache {
+                Some(cache) => cache.load(self, "organism.xml", xml::parse_organisms).await,
+                None => xml::parse_organisms(&self.fetch_xml("organism.xml").await?),
+            }
+        })
+    }
+}
+
+impl Client {
+    /// download the game data for [`sys_info`], it is done once per process,
+    /// later calls return the loaded data.
+    pub async fn init_sys_info(&self) -> Result<Arc<SysInfo>> {
+        let _loading = SYS_INFO_LOADING.lock().await;
+        if let Ok(sys_info) = sys_info() {
+            return Ok(sys_info);
+        }
+        self.load_sys_info().await
+    }
+
+    /// download the game data again and replace the loaded one, e.g. after the game is updated.
+    ///
+    /// the `Arc`s returned before still hold the old data.
+    pub async fn reload_sys_info(&self) -> Result<Arc<SysInfo>> {
+        let _loading = SYS_INFO_LOADING.lock().await;
+        self.load_sys_info().await
+    }
+
+    async fn load_sys_info(&self) -> Result<Arc<SysInfo>> {
+        let sys_info = Arc::new(SysInfo::new(self.get_organisms().await?, self.get_tools().await?));
+        *SYS_INFO.write().unwrap() = Some(sys_info.clone());
+        Ok(sys_info)
+    }
 }
@@ -0,0 +1,184 @@
+//! Cache the parsed game data files on disk, since they are large and rarely change.
+//!
+//! Every file of every server is cached in `{dir}/{host}/{name}.toml`, with the url,
+//! the fetch time, a hash of the content and the validators (`ETag`/`Last-Modified`) of the server.
+//! A cached file younger than `max_age` is used as is, an older one is validated with a
+//! conditional `GET` and only parsed again if the content is changed.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use reqwest::{
+    header::{HeaderMap, HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    StatusCode, Url,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{Client, ErrorKind, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SysCache {
+    dir: PathBuf,
+    max_age: Duration,
+    offline: bool,
+}
+
+impl SysCache {
+    /// cache in `dir`, validate the cached files older than a day.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        SysCache {
+            dir: dir.into(),
+            max_age: Duration::from_secs(24 * 60 * 60),
+            offline: false,
+        }
+    }
+
+    /// validate the cached files older than `max_age` with the server,
+    /// `Duration::ZERO` to validate every time.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// only use the cached files, never download, it fails if a file is not cached.
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// the cached items of the game data file `name` (e.g. `tool.xml`) of the server of `client`,
+    /// downloaded and parsed by `parse` if the cache is missing or stale.
+    pub(crate) async fn load<T, P>(&self, client: &Client, name: &str, parse: P) -> Result<Vec<T>>
+    where
+        T: Serialize + DeserializeOwned,
+        P: FnOnce(&[u8]) -> Result<Vec<T>>,
+    {
+        let url = client.server().xml_url(name)?;
+        let path = self.path_of(&url, name);
+        let cached = CachedFile::<T>::read(&path, &url).await;
+        let now = unix_now();
+
+        if self.offline {
+            return cached
+                .map(|c| c.items)
+                .ok_or_else(|| ErrorKind::Config(format!("离线模式下没有`{}`的缓存 ({:?})", name, path)));
+        }
+        let cached = match cached {
+            Some(c) if now.saturating_sub(c.fetched_at) < self.max_age.as_secs() => return Ok(c.items),
+            cached => cached,
+        };
+
+        let mut headers = HeaderMap::new();
+        if let Some(c) = &cached {
+            let validators = [(IF_NONE_MATCH, &c.etag), (IF_MODIFIED_SINCE, &c.last_modified)];
+            for (name, value) in validators {
+                if let Some(value) = value.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+                    headers.insert(name, value);
+                }
+            }
+        }
+        let (resp, cached) = match (client.fetch_file(name, headers).await, cached) {
+            (Ok(resp), cached) => (resp, cached),
+            // the stale data is better than nothing
+            (Err(e), Some(cached)) if e.is_transient() => return Ok(cached.items),
+            (Err(e), _) => return Err(e),
+        };
+
+        let file = match cached {
+            Some(cached) if resp.status == StatusCode::NOT_MODIFIED => CachedFile {
+                fetched_at: now,
+                ..cached
+            },
+            cached => {
+                let hash = content_hash(&resp.body);
+                let items = match cached {
+                    Some(cached) if cached.hash == hash => cached.items,
+                    _ => parse(&resp.body)?,
+                };
+                let header = |name| resp.headers.get(name).and_then(|v| v.to_str().ok()).map(ToOwned::to_owned);
+                CachedFile {
+                    url: url.to_string(),
+                    fetched_at: now,
+                    hash,
+                    etag: header(ETAG),
+                    last_modified: header(LAST_MODIFIED),
+                    items,
+                }
+            },
+        };
+        file.write(&path).await?;
+        Ok(file.items)
+    }
+
+    fn path_of(&self, url: &Url, name: &str) -> PathBuf {
+        let mut host = url.host_str().unwrap_or("local").to_owned();
+        if let Some(port) = url.port() {
+            host = format!("{}-{}", host, port);
+        }
+        let name = name.strip_suffix(".xml").unwrap_or(name);
+        self.dir.join(host).join(format!("{}.toml", name))
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct CachedFile<T> {
+    /// without the timestamp
+    url: String,
+    /// unix time in seconds
+    fetched_at: u64,
+    hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_modified: Option<String>,
+    items: Vec<T>,
+}
+
+impl<T: Serialize + DeserializeOwned> CachedFile<T> {
+    /// a missing or broken cache, or the cache of another url, is ignored.
+    async fn read(path: &Path, url: &Url) -> Option<Self> {
+        let content = tokio::fs::read(path).await.ok()?;
+        let file: CachedFile<T> = toml::from_slice(&content).ok()?;
+        (file.url == url.as_str()).then_some(file)
+    }
+
+    async fn write(&self, path: &Path) -> Result<()> {
+        let content = toml::Value::try_from(self)
+            .and_then(|v| toml::to_string(&v))
+            .map_err(|e| ErrorKind::Serialize(Box::new(e)))?;
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        Ok(tokio::fs::write(path, content).await?)
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// 64-bit FNV-1a, it only tells whether the content is changed.
+fn content_hash(content: &[u8]) -> String {
+    let hash = content.iter().fold(0xcbf29ce484222325_u64, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("fnv1a64:{:016x}", hash)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_content_hash() {
+        assert_eq!(content_hash(b""), "fnv1a64:cbf29ce484222325");
+        assert_eq!(content_hash(b"a"), "fnv1a64:af63dc4c8601ec8c");
+        assert_ne!(content_hash(b"<tools/>"), content_hash(b"<tools />"));
+    }
+}
@@ -0,0 +1,200 @@
+//! Parse the game data files `php_xml/organism.xml` and `php_xml/tool.xml`.
+//!
+//! Only the attributes used by [`Organism`], [`Evolution`] and [`Tool`] are read,
+//! missing numbers are `0` and missing strings are empty, except for the ids.
+//!
+//! ```xml
+//! <organisms>
+//!   <item id="1" name="豌豆射手" type="1" attribute="火" height="80" width="60" img_id="1">
+//!     <evolutions>
+//!       <item id="1" grade="10" target="2" tool_id="5" money="1000"/>
+//!     </evolutions>
+//!   </item>
+//! </organisms>
+//! <tools>
+//!   <item id="5" name="进化书" img_id="5" type="2" type_name="进化材料"/>
+//! </tools>
+//! ```
+
+use std::{collections::HashMap, str::FromStr};
+
+use quick_xml::{events::{BytesStart, Event}, Reader};
+
+use super::{Evolution, Organism, Tool};
+use crate::{ErrorKind, Result};
+
+pub(crate) fn parse_organisms(xml: &[u8]) -> Result<Vec<Organism>> {
+    let mut organisms: Vec<Organism> = Vec::new();
+    parse_items(xml, "organism.xml", |parent, item| {
+        match parent {
+            b"organisms" => organisms.push(Organism {
+                id: item.required("id")?,
+                name: item.string("name"),
+                organism_type: item.optional("type")?,
+                attribute: item.string("attribute"),
+                height: item.optional("height")?,
+                width: item.optional("width")?,
+                image_id: item.optional("img_id")?,
+                evolutions: Vec::new(),
+            }),
+            b"evolutions" => {
+                let evolution = Evolution {
+                    id: item.required("id")?,
+                    grade: item.optional("grade")?,
+                    target: item.required("target")?,
+                    tool_id: item.optional("tool_id")?,
+                    money: item.optional("money")?,
+                };
+                organisms
+                    .last_mut()
+                    .ok_or_else(|| invalid("organism.xml", "an evolution is outside of organisms"))?
+                    .evolutions
+                    .push(evolution);
+            },
+            _ => {},
+        }
+        Ok(())
+    })?;
+    organisms.sort_by_key(|o| o.id);
+    Ok(organisms)
+}
+
+pub(crate) fn parse_tools(xml: &[u8]) -> Result<Vec<Tool>> {
+    let mut tools = Vec::new();
+    parse_items(xml, "tool.xml", |parent, item| {
+        if parent == b"tools" {
+            tools.push(Tool {
+                tool_id: item.required("id")?,
+                name: item.string("name"),
+                image_id: item.optional("img_id")?,
+                tool_type: item.optional("type")?,
+                type_name: item.string("type_name"),
+            });
+        }
+        Ok(())
+    })?;
+    tools.sort_by_key(|t| t.tool_id);
+    Ok(tools)
+}
+
+pub(in crate::game) fn invalid(file: &'static str, message: impl std::fmt::Display) -> ErrorKind {
+    ErrorKind::protocol(format!("invalid {}: {}", file, message), None)
+}
+
+/// call `on_item` with the name of the parent and the attributes of every `<item>` element.
+pub(in crate::game) fn parse_items<F>(xml: &[u8], file: &'static str, mut on_item: F) -> Result<()>
+where
+    F: FnMut(&[u8], &Item) -> Result<()>,
+{
+    parse_elements(xml, file, |parent, name, item| match name {
+        b"item" => on_item(parent, item),
+        _ => Ok(()),
+    })
+}
+
+/// call `on_element` with the name of the parent, the name and the attributes of every element.
+pub(in crate::game) fn parse_elements<F>(xml: &[u8], file: &'static str, mut on_element: F) -> Result<()>
+where
+    F: FnMut(&[u8], &[u8], &Item) -> Result<()>,
+{
+    let mut reader = Reader::from_bytes(xml);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut path: Vec<Vec<u8>> = Vec::new();
+    loop {
+        let event = reader
+            .read_event(&mut buf)
+            .map_err(|e| invalid(file, format_args!("{} at {}", e, reader.buffer_position())))?;
+        match event {
+            Event::Start(e) => {
+                visit(&reader, file, &e, &path, &mut on_element)?;
+                path.push(e.name().to_vec());
+            },
+            Event::Empty(e) => visit(&reader, file, &e, &path, &mut on_element)?,
+            Event::End(_) => {
+                path.pop();
+            },
+            Event::Eof => return Ok(()),
+            _ => {},
+        }
+        buf.clear();
+    }
+}
+
+fn visit<F>(reader: &Reader<&[u8]>, file: &'static str, e: &BytesStart, path: &[Vec<u8>], on_element: &mut F) -> Result<()>
+where
+    F: FnMut(&[u8], &[u8], &Item) -> Result<()>,
+{
+    let mut attrs = HashMap::new();
+    for attr in e.attributes() {
+        let attr = attr.map_err(|e| invalid(file, e))?;
+        let value = attr.unescape_and_decode_value(reader).map_err(|e| invalid(file, e))?;
+        attrs.insert(String::from_utf8_lossy(attr.key).into_owned(), value);
+    }
+    let parent = path.last().map(Vec::as_slice).unwrap_or_default();
+    on_element(parent, e.name(), &Item { file, attrs })
+}
+
+/// the attributes of an element, usually an `<item>`.
+pub(in crate::game) struct Item {
+    file: &'static str,
+    attrs: HashMap<String, String>,
+}
+
+impl Item {
+    pub(in crate::game) fn required<T: FromStr>(&self, key: &str) -> Result<T> {
+        let value = self.attrs
+            .get(key)
+            .ok_or_else(|| invalid(self.file, format_args!("an item has no `{}`", key)))?;
+        value
+            .trim()
+            .parse()
+            .map_err(|_| invalid(self.file, format_args!("`{}` of an item is `{}`", key, value)))
+    }
+
+    pub(in crate::game) fn optional<T: FromStr + Default>(&self, key: &str) -> Result<T> {
+        match self.attrs.get(key) {
+            Some(value) if !value.trim().is_empty() => self.required(key),
+            _ => Ok(T::default()),
+        }
+    }
+
+    pub(in crate::game) fn string(&self, key: &str) -> String {
+        self.attrs.get(key).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_organisms() -> Result<()> {
+        let organisms = parse_organisms(include_bytes!("../../../test_organism.xml"))?;
+        assert_eq!(organisms.len(), 3);
+        assert_eq!(organisms[0].id, 1);
+        assert_eq!(organisms[0].name, "豌豆射手");
+        assert_eq!(organisms[0].image_id, 101);
+        assert_eq!(organisms[0].evolutions.len(), 2);
+        assert_eq!(organisms[0].evolutions[1].target, 3);
+        assert_eq!(organisms[0].evolutions[1].money, 50000);
+        // missing attributes
+        assert_eq!(organisms[2].attribute, "");
+        assert!(organisms[2].evolutions.is_empty());
+
+        assert!(parse_organisms(b"<organisms><item name=\"x\"/></organisms>").is_err());
+        assert!(parse_organisms(b"<organisms><item id=\"1\"></organisms>").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_tools() -> Result<()> {
+        let tools = parse_tools(include_bytes!("../../../test_tool.xml"))?;
+        assert_eq!(tools.len(), 3);
+        assert_eq!(tools[0].tool_id, 5);
+        assert_eq!(tools[0].name, "进化书");
+        assert_eq!(tools[2].type_name, "品质 & 技能");
+        assert!(parse_tools(b"<tools><item id=\"x\"/></tools>").is_err());
+        Ok(())
+    }
+}
@@ -4,7 +4,10 @@ use crate::game::sys::{Quality};
 
 use serde::Deserialize;
 
-use super::sys::{Organism, get_sys_organisms, Tool, get_sys_tools};
+use super::sys::{Organism, SysInfo, Tool};
+use crate::{transport::BoxFuture, Client};
+
+mod xml;
 
 #[derive(Debug, Deserialize)]
 pub struct UserOrganism {
@@ -26,20 +29,10 @@ pub struct Skill {
 }
 
 impl UserOrganism {
-    pub async fn get_organism(&self) -> Result<&Organism> {
-        let o = get_sys_organisms()?;
-        for organism in o[..self.target_id].iter().rev() {
-            if organism.id == self.target_id {
-                unsafe {
-                    // safe operation, because sys_info's lifetime is almost equal to the whole app.
-                    return Ok(&*(organism as *const Organism));
-                }
-            }
-            if organism.id < self.target_id {
-                break;
-            }
-        }
-        Err(ErrorKind::other_str("unknow organism."))
+    pub fn get_organism<'a>(&self, sys_info: &'a SysInfo) -> Result<&'a Organism> {
+        sys_info
+            .organism(self.target_id)
+            .ok_or(ErrorKind::NotFound { kind: "organism", id: self.target_id })
     }
 }
 
@@ -51,25 +44,42 @@ pub struct UserTool {
 }
 
 impl UserTool {
-    pub async fn get_tool(&self) -> Result<&Tool> {
-        let ts = get_sys_tools().await?;
-        for tool in ts[..self.id].iter().rev() {
-            if tool.tool_id == self.id {
-                unsafe {
-                    // safe operation, because sys_info's lifetime is almost equal to the whole app.
-                    return Ok(&*(tool as *const Tool));
-                }
-            }
-            if tool.tool_id < self.id {
-                break;
-            }
-        }
-        Err(ErrorKind::other_str("unknow tool."))
+    pub fn get_tool<'a>(&self, sys_info: &'a SysInfo) -> Result<&'a Tool> {
+        sys_info
+            .tool(self.id)
+            .ok_or(ErrorKind::NotFound { kind: "tool", id: self.id })
     }
 }
 
 pub trait GetUserInfo {
+    // /pvz/index.php/default/user/sig/{sig}?{timestamp}
+    fn get_user(&self) -> BoxFuture<'_, Result<GameUser>>;
+
     // /pvz/index.php/Warehouse/index/sig/11c58a61121e4a8b1f77abf6f0f5a1fa?1660726559561
-    fn get_warehouse(&self) -> (Vec<UserTool>, Vec<UserOrganism>);
+    fn get_warehouse(&self) -> BoxFuture<'_, Result<(Vec<UserTool>, Vec<UserOrganism>)>>;
+}
+
+impl GetUserInfo for Client {
+    fn get_user(&self) -> BoxFuture<'_, Result<GameUser>> {
+        Box::pin(async move { xml::parse_user(&self.fetch_page("default/user").await?) })
+    }
+
+    fn get_warehouse(&self) -> BoxFuture<'_, Result<(Vec<UserTool>, Vec<UserOrganism>)>> {
+        Box::pin(async move { xml::parse_warehouse(&self.fetch_page("Warehouse/index").await?) })
+    }
 }
 
+
+impl Client {
+    /// fetch the profile of the player, and keep it for [`Client::user`].
+    pub async fn refresh_user(&self) -> Result<GameUser> {
+        let user = self.get_user().await?;
+        *self.user.lock().unwrap() = Some(user.clone());
+        Ok(user)
+    }
+
+    /// the profile fetched by the last [`Client::refresh_user`].
+    pub fn user(&self) -> Option<GameUser> {
+        self.user.lock().unwrap().clone()
+    }
+}
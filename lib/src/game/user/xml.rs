@@ -0,0 +1,160 @@
+//! Parse the user page `index.php/default/user/sig/{sig}` and the warehouse page
+//! `index.php/Warehouse/index/sig/{sig}`.
+//!
+//! The layouts below (and the `test_user.xml` and `test_warehouse.xml` fixtures) are not yet
+//! checked against pages captured from the game, replace the fixtures with captured pages once
+//! there are some.
+//!
+//! ```xml
+//! <root>
+//!   <user id="8812345" name="玩家" grade="56" exp="123456" money="2500000"
+//!         rmb_money="320" vip_grade="3" honor="1500">
+//!     <challenge fuben="5" stone="10"/>
+//!   </user>
+//! </root>
+//! ```
+//!
+//! ```xml
+//! <root>
+//!   <warehouse>
+//!     <tools>
+//!       <item id="30" amount="12"/>
+//!     </tools>
+//!     <organisms>
+//!       <item id="21034567" pid="2" quality="传说">
+//!         <skills>
+//!           <item id="101" name="穿刺" grade="3"/>
+//!         </skills>
+//!         <special_skills>
+//!           <item id="901" name="魔神之力" grade="2"/>
+//!         </special_skills>
+//!       </item>
+//!     </organisms>
+//!   </warehouse>
+//! </root>
+//! ```
+
+use super::{Skill, UserOrganism, UserTool};
+use crate::game::{sys::xml::{invalid, parse_elements, parse_items, Item}, GameUser, Result};
+
+const FILE: &str = "warehouse";
+
+pub(crate) fn parse_user(xml: &[u8]) -> Result<GameUser> {
+    let mut user = None;
+    parse_elements(xml, "user", |_, name, item| {
+        match name {
+            b"user" => user = Some(GameUser {
+                id: item.required("id")?,
+                name: item.string("name"),
+                grade: item.optional("grade")?,
+                exp: item.optional("exp")?,
+                money: item.optional("money")?,
+                diamonds: item.optional("rmb_money")?,
+                vip_grade: item.optional("vip_grade")?,
+                honor: item.optional("honor")?,
+                ..Default::default()
+            }),
+            b"challenge" => {
+                let user = user
+                    .as_mut()
+                    .ok_or_else(|| invalid("user", "the challenges are outside of the user"))?;
+                user.challenges.fuben = item.optional("fuben")?;
+                user.challenges.stone = item.optional("stone")?;
+            },
+            _ => {},
+        }
+        Ok(())
+    })?;
+    // an error page of the game has no user
+    user.ok_or_else(|| invalid("user", "there is no user"))
+}
+
+pub(crate) fn parse_warehouse(xml: &[u8]) -> Result<(Vec<UserTool>, Vec<UserOrganism>)> {
+    // an error page of the game has no warehouse, it is not an empty one.
+    if !xml.windows(b"<warehouse".len()).any(|w| w == b"<warehouse") {
+        return Err(invalid(FILE, "there is no warehouse"));
+    }
+
+    let mut tools = Vec::new();
+    let mut organisms: Vec<UserOrganism> = Vec::new();
+    parse_items(xml, FILE, |parent, item| {
+        match parent {
+            b"tools" => tools.push(UserTool {
+                id: item.required("id")?,
+                amount: item.optional("amount")?,
+            }),
+            b"organisms" => organisms.push(UserOrganism {
+                id: item.required("id")?,
+                target_id: item.required("pid")?,
+                quality: item.required("quality")?,
+                skills: Vec::new(),
+                special_skill: None,
+            }),
+            b"skills" => last(&mut organisms)?.skills.push(skill(item)?),
+            b"special_skills" => last(&mut organisms)?.special_skill = Some(skill(item)?),
+            _ => {},
+        }
+        Ok(())
+    })?;
+    Ok((tools, organisms))
+}
+
+fn last(organisms: &mut [UserOrganism]) -> Result<&mut UserOrganism> {
+    organisms
+        .last_mut()
+        .ok_or_else(|| invalid(FILE, "a skill is outside of organisms"))
+}
+
+fn skill(item: &Item) -> Result<Skill> {
+    Ok(Skill {
+        id: item.required("id")?,
+        name: item.string("name"),
+        grade: item.optional("grade")?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::sys::Quality;
+
+    #[test]
+    fn test_parse_user() -> Result<()> {
+        let user = parse_user(include_bytes!("../../../test_user.xml"))?;
+        assert_eq!(user.id, 8812345);
+        assert_eq!(user.name, "玩家");
+        assert_eq!(user.grade, 56);
+        assert_eq!(user.money, 2500000);
+        assert_eq!(user.diamonds, 320);
+        assert_eq!(user.vip_grade, 3);
+        assert_eq!(user.honor, 1500);
+        assert_eq!((user.challenges.fuben, user.challenges.stone), (5, 10));
+
+        assert_eq!(parse_user(b"<user id=\"1\"/>")?.money, 0);
+        assert!(parse_user(b"<root><response><status>error</status></response></root>").is_err());
+        assert!(parse_user(b"<user id=\"1\" money=\"-\"/>").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_warehouse() -> Result<()> {
+        let (tools, organisms) = parse_warehouse(include_bytes!("../../../test_warehouse.xml"))?;
+        assert_eq!(tools.len(), 3);
+        assert_eq!((tools[0].id, tools[0].amount), (30, 12));
+        assert_eq!(organisms.len(), 2);
+
+        let organism = &organisms[0];
+        assert_eq!((organism.id, organism.target_id), (21034567, 2));
+        assert_eq!(organism.quality, Quality::传说);
+        assert_eq!(organism.skills.len(), 2);
+        assert_eq!(organism.skills[1].name, "连击");
+        assert_eq!(organism.special_skill.as_ref().map(|s| s.grade), Some(2));
+        assert!(organisms[1].skills.is_empty());
+        assert!(organisms[1].special_skill.is_none());
+
+        assert!(parse_warehouse(b"<root><warehouse/></root>")?.0.is_empty());
+        assert!(parse_warehouse(b"<root><response><status>error</status></response></root>").is_err());
+        assert!(parse_warehouse(b"<warehouse><organisms><item id=\"1\" pid=\"1\" quality=\"x\"/></organisms></warehouse>").is_err());
+        Ok(())
+    }
+}
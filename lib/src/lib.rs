@@ -1,59 +1,67 @@
-use std::{collections::{HashMap}, time::Duration, io::Write};
+use std::{collections::{HashMap}, future::Future, sync::{Arc, Mutex}, time::{Duration, SystemTime, UNIX_EPOCH}};
 
-use crate::amf::{amf0::{array, number, string}, Value, Amf0Value, packet::{Body, Packet, ReadAs}};
+use bytes::Bytes;
 
-use crate::amf::{TryAsAmf0Object, TryAsBoolean, TryAsNumber};
-use game::sys::{Quality, ChallengeType, QualityUpType};
-use rand::Rng;
+use crate::amf::{Value, packet::{IntoBytes, Packet, RemoteFault, Response}};
+use crate::cancel::CancellationToken;
+use crate::cookie::CookieJar;
+use crate::event::{Event, EventHandler, Progress};
+use crate::pacing::{Pacer, Pacing};
+use crate::retry::RetryPolicy;
+use crate::server::{Server, ServerRegistry};
+use crate::session::Reauth;
+use crate::transport::{AmfRequest, AmfResponse, AmfTransport, HttpOptions, ReqwestTransport};
+
+use game::{sys::{Quality, ChallengeType, QualityUpType, SysCache}, GameUser};
 use reqwest::{header, Url};
+use serde::{de::{DeserializeOwned, IgnoredAny}, Deserialize, Serialize};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 
 pub use account::*;
+pub use batch::Batch;
+pub use error::{ErrorKind, Rejection};
 
 pub type Result<T,E = ErrorKind> = std::result::Result<T,E>;
 
 pub mod amf;
+pub mod cancel;
+pub mod cookie;
+pub mod event;
 pub mod game;
+pub mod pacing;
+pub mod retry;
+pub mod server;
+pub mod transport;
 
 mod account;
+mod batch;
+mod error;
+mod session;
+mod sig;
 
-#[derive()]
-pub enum ErrorKind {
-    Static(&'static str),
-    Owned(String),
-}
-
-impl From<String> for ErrorKind {
-    fn from(s: String) -> Self {
-        Self::Owned(s)
-    }
-}
-
-impl From<&'static str> for ErrorKind {
-    fn from(s: &'static str) -> Self {
-        Self::Static(s)
-    }
-}
-
-impl std::fmt::Display for ErrorKind {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        use ErrorKind::*;
-        let s = match self {
-            Static(s) => s,
-            Owned(s) => s.as_str(),
-        };
-        s.fmt(f)
-    }
-}
+#[cfg(test)]
+mod tests;
 
 pub struct Client {
-    reqwest_client: reqwest::Client,
-    #[allow(dead_code)] server: u8,
-    server_url: Url,
-    cookies: String,
+    transport: Arc<dyn AmfTransport>,
+    pacer: Pacer,
+    retry: RetryPolicy,
+    on_event: Option<EventHandler>,
+    subscribers: Mutex<Vec<UnboundedSender<Event>>>,
+    cancel: CancellationToken,
+    reauth: Option<Reauth>,
+    server: Server,
+    amf_url: Url,
+    /// sent with every request, see [`HttpOptions`].
+    headers: header::HeaderMap,
+    cookies: Mutex<CookieJar>,
+    sys_cache: Option<SysCache>,
+    sig_key: Option<String>,
+    user: Mutex<Option<GameUser>>,
+    /// the account the client is built from, without the cookies.
+    account: AccountInfo,
 }
 
-static ERR_PARSE_AMF_OBJ: &'static str = "无法将返回的数据解析为`Amf0Value::Object`";
-
 impl Client {
     
     #[inline]
@@ -61,91 +69,328 @@ impl Client {
         ClientBuilder::new()
     }
 
+    /// the base url of the youkia server `server_id`, see [`Server::youkia`].
     pub fn resolve_server(server_id: u8) -> Url {
-        let url = if server_id < 12 {
-            format!("http://pvz-s{}.youkia.com", server_id)
-        } else {
-            format!("http://s{}.youkia.pvz.youkia.com", server_id)
-        };
-        Url::parse(&url).expect("fail to parse server host!")
+        Server::youkia(server_id).base_url
     }
 
-    #[inline]
-    pub(crate) fn amf_request_path(&self) -> Url {
-        let url = self.server_url.join("/pvz/amf/").unwrap();
-        url
+    pub fn server(&self) -> &Server {
+        &self.server
     }
 
     pub fn server_url(&self) -> &Url {
-        &self.server_url
+        &self.server.base_url
     }
 
-    pub fn cookies(&self) -> impl Iterator<Item = (&str,&str)> {
-        self.cookies
-            .split(";")
-            .filter(|s| !s.is_empty())
-            .map(|s| {
-                if let Some(n) = s.find('=') {
-                    return s.split_at(n);
-                }
-                unreachable!("key/value pairs are joined with '='");
-            })
+    /// a snapshot of the current cookies.
+    pub fn cookies(&self) -> CookieJar {
+        self.cookies.lock().unwrap().clone()
     }
 
-    pub fn get_cookie(&self, key: &str) -> Option<&str> {
-        self.cookies().find_map(|(k,v)| {
-            (key == k).then(|| v)
-        })
+    pub fn get_cookie(&self, key: &str) -> Option<String> {
+        self.cookies.lock().unwrap().get(key).map(ToOwned::to_owned)
     }
 
     /// add or set the cookie by key, return old value
-    pub fn set_cookie<S: AsRef<str>, V: AsRef<str>>(&mut self, key: S, new_value: V) -> Option<String> {
-        let val_ref = self.get_cookie(key.as_ref());
-
-        if let Some(val) = val_ref {
-            let old_val = val.to_owned();
-            unsafe {
-                let start = val.as_ptr().offset_from(self.cookies.as_ptr()) as usize;
-                let end = start + val.len();
-                self.cookies.replace_range(start..end, new_value.as_ref());
-            }
-            return Some(old_val);
-        }
+    pub fn set_cookie(&self, key: impl Into<String>, new_value: impl Into<String>) -> Option<String> {
+        self.cookies.lock().unwrap().set(key, new_value)
+    }
+
+    /// remove the cookie by key, return old value
+    pub fn remove_cookie(&self, key: &str) -> Option<String> {
+        self.cookies.lock().unwrap().remove(key)
+    }
 
-        None
+    /// the account of the client with the current cookies, which can be saved with `AccountInfo::save`.
+    ///
+    /// the other fields are the ones of [`ClientBuilder::account`], the server is `0` (not saved)
+    /// if the client is built from a url only.
+    pub fn account_info(&self) -> AccountInfo {
+        AccountInfo {
+            cookies: self.cookies().into(),
+            ..self.account.clone()
+        }
     }
 
-    pub(crate) async fn send_amf<V: Into<Value>>(
+    /// send a single call to the amf gateway, and return the whole response packet.
+    pub async fn send_amf<V: Into<Value>>(
         &self,
         target_uri: &str,
         response_uri: &str,
         data: V
-    ) -> Result<Packet<'_>> {
+    ) -> Result<Packet> {
         let req_packet = Packet::builder()
             .with_default_version()
             .body(target_uri, response_uri, data)
             .build()
-            .map_err(|e|{
-                format!("fail to build packet: {}", e)
-            })?;
-        let resp: Packet<'_> = self.reqwest_client
-            .post(self.amf_request_path())
-            .header(header::COOKIE, &self.cookies)
-            .header("x-flash-version", "34,0,0,192")
-            .header(header::CONTENT_TYPE, "application/x-amf")
-            .body(req_packet)
-            .send()
-            .await
-            .map_err(|e| e.to_string())?
-            .bytes()
-            .await
-            .map_err(|e| e.to_string())?
-            .read_as()
-            .map_err(|e| {
-                format!("fail to parse response as AMF packet: {}", e)
-            })?;
+            .map_err(|e| ErrorKind::protocol(format!("fail to build packet: {}", e), None))?;
+        self.send_packet(req_packet).await
+    }
+
+    /// send a packet to the amf gateway, and return the response packet.
+    ///
+    /// If the session is expired and a re-auth callback is set by [`ClientBuilder::reauth`],
+    /// the cookies are refreshed and the packet is sent once more.
+    /// It is retried only if it is not sent, see [`retry`](crate::retry).
+    pub async fn send_packet(&self, req_packet: Packet) -> Result<Packet> {
+        self.send_packet_with(req_packet, false).await
+    }
+
+    /// `idempotent`: retry all the transient failures, even if the packet may have reached the server.
+    async fn send_packet_with(&self, req_packet: Packet, idempotent: bool) -> Result<Packet> {
+        let body = req_packet.into_bytes();
+        match (self.send_with_retry(&body, idempotent).await, &self.reauth) {
+            (Err(ErrorKind::SessionExpired(reason)), Some(reauth)) => {
+                self.emit(Event::Reauth { reason });
+                let cookies = reauth().await?;
+                {
+                    let mut jar = self.cookies.lock().unwrap();
+                    for (k, v) in cookies {
+                        jar.set(k, v);
+                    }
+                }
+                self.send_with_retry(&body, idempotent).await
+            },
+            (res, _) => res,
+        }
+    }
+
+    async fn send_with_retry(&self, body: &Bytes, idempotent: bool) -> Result<Packet> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            self.pacer.wait().await;
+            let res = self.send_request(AmfRequest {
+                url: self.amf_url.clone(),
+                headers: self.request_headers()?,
+                body: body.clone(),
+            }).await;
+
+            match (res, self.retry.backoff_after(attempt)) {
+                (Err(e), Some(delay)) if e.is_unsent() || (idempotent && e.is_transient()) => {
+                    self.emit(Event::Retry {
+                        attempt,
+                        delay,
+                        error: e.to_string(),
+                    });
+                    // wake up early if cancelled
+                    let _ = tokio::time::timeout(delay, self.cancel.cancelled()).await;
+                    if self.cancel.is_cancelled() {
+                        return Err(ErrorKind::Cancelled);
+                    }
+                },
+                (res, _) => return res,
+            }
+        }
+    }
+
+    fn request_headers(&self) -> Result<header::HeaderMap> {
+        use header::{HeaderValue, CONTENT_TYPE, COOKIE, REFERER};
+
+        let mut headers = header::HeaderMap::new();
+        let cookies = self.cookies.lock().unwrap().header_value();
+        headers.insert(COOKIE, HeaderValue::from_str(&cookies)
+            .map_err(|e| ErrorKind::Config(format!("invalid cookies: {}", e)))?);
+        headers.insert("x-flash-version", HeaderValue::from_str(&self.server.flash_version)
+            .map_err(|e| ErrorKind::Config(format!("invalid flash version: {}", e)))?);
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/x-amf"));
+        headers.insert(REFERER, HeaderValue::from_str(self.server.base_url.join("main.swf").unwrap().as_str())
+            .map_err(|e| ErrorKind::Config(format!("invalid referer: {}", e)))?);
+        for (k, v) in &self.headers {
+            headers.insert(k, v.clone());
+        }
+        Ok(headers)
+    }
+
+    /// `GET` the game data file `name` (e.g. `tool.xml`), with a timestamp to bust the cache like the game does.
+    pub(crate) async fn fetch_xml(&self, name: &str) -> Result<Bytes> {
+        Ok(self.fetch_file(name, header::HeaderMap::new()).await?.body)
+    }
+
+    /// like [`Client::fetch_xml`], with `headers` added to the request, a `304 Not Modified` response is returned as is.
+    pub(crate) async fn fetch_file(&self, name: &str, headers: header::HeaderMap) -> Result<AmfResponse> {
+        let mut url = self.server.xml_url(name)?;
+        url.set_query(Some(&unix_millis().to_string()));
+        self.fetch(url, headers).await
+    }
+
+    /// `GET` the php page `page` (e.g. `Warehouse/index`) signed by the sig key, see [`ClientBuilder::sig_key`].
+    pub(crate) async fn fetch_page(&self, page: &str) -> Result<Bytes> {
+        let key = self.sig_key
+            .as_deref()
+            .ok_or_else(|| ErrorKind::Config("没有配置`sig_key`, 无法访问该页面".to_owned()))?;
+        let timestamp = unix_millis();
+        let mut url = self.server.page_url(&format!("{}/sig/{}", page, sig::sign(key, timestamp)))?;
+        url.set_query(Some(&timestamp.to_string()));
+        Ok(self.fetch(url, header::HeaderMap::new()).await?.body)
+    }
+
+    async fn fetch(&self, url: Url, headers: header::HeaderMap) -> Result<AmfResponse> {
+        self.pacer.wait().await;
+        let mut request_headers = self.request_headers()?;
+        request_headers.remove(header::CONTENT_TYPE);
+        request_headers.extend(headers);
+        let resp = self.transport.fetch(AmfRequest { url, headers: request_headers, body: Bytes::new() }).await?;
+        if resp.status == reqwest::StatusCode::NOT_MODIFIED {
+            return Ok(resp);
+        }
+        // the pages redirect to the login page, the files are xml so the html check does not apply.
+        if resp.status.is_redirection() {
+            let reason = session::check_response(&resp).unwrap_or_default();
+            return Err(ErrorKind::SessionExpired(reason));
+        }
+        if !resp.status.is_success() {
+            return Err(ErrorKind::HttpStatus(resp.status));
+        }
+        if resp.body.is_empty() {
+            return Err(ErrorKind::EmptyResponse);
+        }
         Ok(resp)
-    } 
+    }
+
+    async fn send_request(&self, request: AmfRequest) -> Result<Packet> {
+        let mut resp = self.transport.send(request).await?;
+        {
+            let mut cookies = self.cookies.lock().unwrap();
+            for set_cookie in resp.headers.get_all(header::SET_COOKIE) {
+                if let Ok(set_cookie) = set_cookie.to_str() {
+                    cookies.apply_set_cookie(set_cookie);
+                }
+            }
+        }
+        if let Some(reason) = session::check_response(&resp) {
+            return Err(ErrorKind::SessionExpired(reason));
+        }
+        if !resp.status.is_success() {
+            return Err(ErrorKind::HttpStatus(resp.status));
+        }
+        if resp.body.is_empty() {
+            return Err(ErrorKind::EmptyResponse);
+        }
+
+        let packet = Packet::read_from(&mut resp.body)?;
+        if let Some(reason) = session::check_packet(&packet) {
+            return Err(ErrorKind::SessionExpired(reason));
+        }
+        Ok(packet)
+    }
+
+    /// receive the events of the client from now on, like the handler set by [`ClientBuilder::on_event`].
+    pub fn subscribe(&self) -> UnboundedReceiver<Event> {
+        let (tx, rx) = unbounded_channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    pub(crate) fn emit(&self, event: Event) {
+        if let Some(on_event) = &self.on_event {
+            on_event(&event);
+        }
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.send(event.clone()).is_ok());
+    }
+
+    /// the token which stops the loops, see [`ClientBuilder::cancellation`].
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.cancel
+    }
+
+    /// run a step of a loop, backing off with the retry policy while the game says it is too frequent.
+    ///
+    /// `Ok(None)` means the loop should stop, since the game says something is used up,
+    /// or `cancel` (the token of the loop) or the client is cancelled.
+    async fn loop_step<T, F, Fut>(&self, cancel: &CancellationToken, mut step: F) -> Result<Option<T>>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        for attempt in 1.. {
+            if cancel.is_cancelled() || self.cancel.is_cancelled() {
+                self.emit(Event::Progress(Progress::Cancelled));
+                return Ok(None);
+            }
+            let error = match step().await {
+                Ok(v) => return Ok(Some(v)),
+                Err(ErrorKind::Cancelled) => {
+                    self.emit(Event::Progress(Progress::Cancelled));
+                    return Ok(None);
+                },
+                Err(e) => e,
+            };
+            match error.rejection() {
+                Some(reason) if reason.is_exhausted() => {
+                    self.emit(Event::Progress(Progress::Stopped {
+                        reason,
+                        message: error.to_string(),
+                    }));
+                    return Ok(None);
+                },
+                Some(Rejection::TooFrequent) => {
+                    let delay = match self.retry.backoff_after(attempt) {
+                        Some(delay) => delay,
+                        None => return Err(error),
+                    };
+                    self.emit(Event::Retry {
+                        attempt,
+                        delay,
+                        error: error.to_string(),
+                    });
+                    // wake up early if cancelled
+                    let _ = tokio::time::timeout(delay, crate::cancel::either_cancelled(cancel, &self.cancel)).await;
+                },
+                _ => return Err(error),
+            }
+        }
+        unreachable!()
+    }
+
+    /// call `target_uri` with `args` (usually a tuple) as the arguments,
+    /// and deserialize the result into `R`.
+    pub async fn call<A, R>(
+        &self,
+        target_uri: &str,
+        args: A,
+    ) -> Result<R>
+    where
+        A: Serialize,
+        R: DeserializeOwned,
+    {
+        self.call_with(target_uri, args, false).await
+    }
+
+    /// like [`Client::call`], but also retried if it fails after it is sent,
+    /// only for the calls which are safe to repeat, e.g. queries.
+    pub async fn call_idempotent<A, R>(
+        &self,
+        target_uri: &str,
+        args: A,
+    ) -> Result<R>
+    where
+        A: Serialize,
+        R: DeserializeOwned,
+    {
+        self.call_with(target_uri, args, true).await
+    }
+
+    async fn call_with<A, R>(&self, target_uri: &str, args: A, idempotent: bool) -> Result<R>
+    where
+        A: Serialize,
+        R: DeserializeOwned,
+    {
+        let args = amf::to_value(&args)
+            .map_err(|e| ErrorKind::protocol("fail to serialize arguments", e))?;
+        let req_packet = Packet::builder()
+            .with_default_version()
+            .body(target_uri, "/1", args)
+            .build()
+            .map_err(|e| ErrorKind::protocol(format!("fail to build packet: {}", e), None))?;
+
+        let mut res = self.send_packet_with(req_packet, idempotent).await?;
+
+        decode_response(res.take_response("/1"), "/1")
+    }
 
     /// 技能升级
     /// 
@@ -155,31 +400,17 @@ impl Client {
         plant_id: f64,
         skill_id: f64,
     ) -> Result<f64> {
-
-        let res: Packet = self.send_amf(
-            "api.apiorganism.skillUp", 
-            "/1",
-            array(vec![number(plant_id), number(skill_id)])
-        ).await?;
-
-        let Body { data, .. } = res.bodies
-            .first()
-            .ok_or("response packet body is empty.")?;
-
-        let data = data.try_as_amf0_object().ok_or(ERR_PARSE_AMF_OBJ)?;
-
-        let now_id = data.get("now_id");
-
-        if now_id.is_none() {
-            return get_error_from_map(&data, "返回数据中无所需的Number'now_id'".into());
+        #[derive(Deserialize)]
+        struct SkillUp {
+            now_id: f64,
         }
 
-        match now_id.unwrap() {
-            &&Amf0Value::Number(now_id) => Ok(now_id),
-            &&Amf0Value::String(ref now_id) => Ok(now_id.parse().unwrap()),
-            _ => Err("无法将'now_id'解析为数字".into()),
-        }
+        let SkillUp { now_id } = self.call(
+            "api.apiorganism.skillUp",
+            (plant_id, skill_id),
+        ).await?;
 
+        Ok(now_id)
     }
 
     pub async fn skill_up_to(
@@ -187,23 +418,27 @@ impl Client {
         plant_id: f64,
         mut skill_id: f64,
         until: impl Fn(usize, u32)->bool,
+        cancel: &CancellationToken,
     ) -> Result<()> {
         'outer: for up in 0.. {
             for i in 1.. {
                 if until(i, up) {
                     break 'outer;
                 }
-                if i != 1 {
-                    wait_a_moment().await;
-                }
-                let new_skill_id = self.skill_up(plant_id, skill_id).await?;
+                let new_skill_id = match self.loop_step(cancel, || self.skill_up(plant_id, skill_id)).await? {
+                    Some(id) => id,
+                    None => break 'outer,
+                };
+                self.emit(Event::Progress(Progress::SkillUp {
+                    plant_id,
+                    attempt: i,
+                    old: skill_id,
+                    new: new_skill_id,
+                }));
                 if new_skill_id != skill_id {
-                    println!("\rtry {:-3} : {} -> {} !", i, skill_id, new_skill_id);
                     skill_id = new_skill_id;
                     break;
                 }
-                print!("\rtry {:-3} : {}", i, new_skill_id);
-                std::io::stdout().flush().map_err(|e| format!("fail to flush stdout: {}", e))?;
             }
         }
         Ok(())
@@ -217,31 +452,20 @@ impl Client {
         quality_up_type: QualityUpType,
         plant_id: f64,
     ) -> Result<Quality> {
+        #[derive(Deserialize)]
+        struct QualityUp {
+            quality_name: Quality,
+        }
 
-        let res: Packet = self.send_amf(
+        let QualityUp { quality_name } = self.call(
             match quality_up_type {
                 QualityUpType::General => "api.apiorganism.qualityUp",
                 QualityUpType::Moshen => "api.apiorganism.quality12Up",
-            }, 
-            "/1",
-            array(vec![number(plant_id)])
+            },
+            (plant_id,),
         ).await?;
 
-        let Body { data, .. } = res.bodies
-            .first()
-            .ok_or("response packet body is empty.")?;
-
-        if let Value::Amf0(Amf0Value::Object{entries, ..}) = data {
-            let entries = entries.into_iter()
-                .map(|p| (p.key.as_str(), &p.value));
-            let data: HashMap<&str, &Amf0Value> = HashMap::from_iter(entries);
-            if let Some(Amf0Value::String(newq)) = data.get("quality_name") {
-                return Ok(newq.parse()?);
-            }
-            return get_error_from_map(&data, "未知错误：返回数据中无'quality_name'".into());
-        }
-
-        Err(ERR_PARSE_AMF_OBJ.into())
+        Ok(quality_name)
     }
 
     pub async fn quality_up_to(
@@ -249,20 +473,20 @@ impl Client {
         quality_up_type: QualityUpType,
         plant_id: f64,
         until: impl Fn(usize, Quality) -> bool,
+        cancel: &CancellationToken,
     ) -> Result<()> {
-        println!("------ START {} ------", plant_id);
         let mut pre = None;
         for i in 1.. {
-            if i != 1 {
-                wait_a_moment().await;
-            }
-            let new_quality = self.quality_up(quality_up_type, plant_id).await?;
-            if pre.is_some() && new_quality != pre.unwrap() {
-                println!("\rtry {:-3} : -> {} !", i, new_quality);
-            } else {
-                print!("\rtry {:-3} : {}", i, new_quality);
-            }
-            std::io::stdout().flush().map_err(|e| format!("fail to flush stdout: {}", e))?;
+            let new_quality = match self.loop_step(cancel, || self.quality_up(quality_up_type, plant_id)).await? {
+                Some(quality) => quality,
+                None => break,
+            };
+            self.emit(Event::Progress(Progress::QualityUp {
+                plant_id,
+                attempt: i,
+                old: pre,
+                new: new_quality,
+            }));
 
             if until(i, new_quality) {
                 break;
@@ -278,42 +502,43 @@ impl Client {
         box_id: f64,
         amount: u32,
     ) -> Result<()> {
-        let res = self.send_amf(
-            "api.reward.openbox",
-            "/1",
-            array(vec![number(box_id), number(amount as f64)]),
-        ).await?;
-
-        if res.bodies.len() == 0 {
-            Err("response packet body is empty.")?;
+        #[derive(Deserialize)]
+        struct OpenBox {
+            #[allow(dead_code)]
+            tools: IgnoredAny,
         }
-        let ref data = res.bodies[0].data;
 
-        let data = data.try_as_amf0_object().ok_or(ERR_PARSE_AMF_OBJ)?;
-
-        if data.get("tools").is_some() {
-            return Ok(());
-        }
+        let _: OpenBox = self.call(
+            "api.reward.openbox",
+            (box_id, amount),
+        ).await?;
 
-        get_error_from_map(&data, "未知错误：返回数据中无`tools`".into())
+        Ok(())
     }
 
+    /// open `amount` boxes `repeat` times.
+    ///
+    /// **@return**: the number of opened boxes
     pub async fn open_box_repeat(
         &self,
         box_id: f64,
         amount: u32,
         repeat: usize,
-    ) -> Result<()> {
-        println!("warning: 提示信息有待优化");
+        cancel: &CancellationToken,
+    ) -> Result<usize> {
+        let mut opened = 0;
         for i in 1..=repeat {
-            if i != 1 {
-                wait_a_moment().await;
+            if self.loop_step(cancel, || self.open_box(box_id, amount)).await?.is_none() {
+                break;
             }
-            self.open_box(box_id, amount).await?;
-            println!("\rNo.{:-4 } 成功开启{}个", i, amount);
+            opened += amount as usize;
+            self.emit(Event::Progress(Progress::BoxOpened {
+                box_id,
+                round: i,
+                amount,
+            }));
         }
-        println!("共开启{}个", amount as usize * repeat);
-        Ok(())
+        Ok(opened)
     }
 
     pub async fn get_duty_reward(
@@ -321,44 +546,103 @@ impl Client {
         duty_id: f64,
         duty_catogary_id: f64
     ) -> Result<()> {
-        let res = self.send_amf(
+        #[derive(Deserialize)]
+        struct DutyReward {
+            #[allow(dead_code)]
+            user_exp: IgnoredAny,
+        }
+
+        let _: DutyReward = self.call(
             "api.duty.reward",
-            "/1",
-            array(vec![number(duty_id), number(duty_catogary_id)]),
+            (duty_id, duty_catogary_id),
         ).await?;
 
-        let Body { data, .. } = res.bodies
-            .first()
-            .ok_or("response packet body is empty.")?;
-
-        let data = data.try_as_amf0_object().ok_or(ERR_PARSE_AMF_OBJ)?;
-
-        if data.get("user_exp").is_some() {
-            return Ok(());
-        }
-
-        get_error_from_map(&data, "未知错误：返回数据中无`user_exp`".into())
+        Ok(())
     }
 
+    /// claim the rewards of all `duty_ids` in a single request, a failed duty does not stop the others.
+    ///
+    /// The duties which are too frequent are sent again after the backoff of the retry policy,
+    /// unless the client is cancelled.
+    ///
+    /// **@return**: the number of claimed rewards, or the error of the first duty if none is claimed
     pub async fn get_duty_rewards(
         &self,
         duty_ids: impl Iterator<Item = f64>,
         duty_catogary_id: f64
-    ) -> Result<()> {
-        for (i, duty_id) in duty_ids.enumerate() {
-            if i != 0 {
-                wait_a_moment().await;
-                // tokio::time::sleep(Duration::from_millis(500)).await;
-            }
-            let res = self.get_duty_reward(duty_id, duty_catogary_id).await;
+    ) -> Result<usize> {
+        #[derive(Deserialize)]
+        struct DutyReward {
+            #[allow(dead_code)]
+            user_exp: IgnoredAny,
+        }
 
-            if let Err(e) = res {
-                eprintln!("{}",e)
-            } else {
-                println!("get reward : {:-5} in {}", duty_id, duty_catogary_id);
-            }
+        let pending = Mutex::new(duty_ids.collect::<Vec<f64>>());
+        let claimed = Mutex::new(0);
+        let first_error = Mutex::new(None);
+        let res = {
+            let (pending, claimed, first_error) = (&pending, &claimed, &first_error);
+            self.loop_step(&self.cancel, move || async move {
+                let duty_ids = std::mem::take(&mut *pending.lock().unwrap());
+                if duty_ids.is_empty() {
+                    return Ok(());
+                }
+                let results = duty_ids
+                    .iter()
+                    .fold(self.batch(), |batch, duty_id| {
+                        batch.call("api.duty.reward", (duty_id, duty_catogary_id))
+                    })
+                    .send_as::<DutyReward>()
+                    .await?;
+
+                let mut too_frequent = None;
+                for (duty_id, res) in duty_ids.into_iter().zip(results) {
+                    let error = match res {
+                        Ok(_) => {
+                            *claimed.lock().unwrap() += 1;
+                            None
+                        },
+                        Err(e) if e.rejection() == Some(Rejection::TooFrequent) => {
+                            pending.lock().unwrap().push(duty_id);
+                            too_frequent.get_or_insert(e);
+                            continue;
+                        },
+                        Err(e) => Some(e),
+                    };
+                    self.emit(Event::Progress(Progress::DutyReward {
+                        duty_id,
+                        category_id: duty_catogary_id,
+                        error: error.as_ref().map(|e| e.to_string()),
+                    }));
+                    if let Some(e) = error {
+                        first_error.lock().unwrap().get_or_insert(e);
+                    }
+                }
+                // back off and send the too frequent ones again
+                too_frequent.map_or(Ok(()), Err)
+            }).await
+        };
+
+        match res {
+            // still too frequent after the backoff, the rest of the duties fail
+            Err(e) if e.rejection() == Some(Rejection::TooFrequent) => {
+                for duty_id in pending.lock().unwrap().drain(..) {
+                    self.emit(Event::Progress(Progress::DutyReward {
+                        duty_id,
+                        category_id: duty_catogary_id,
+                        error: Some(e.to_string()),
+                    }));
+                }
+                first_error.lock().unwrap().get_or_insert(e);
+            },
+            Err(e) => return Err(e),
+            Ok(_) => {},
+        }
+        let claimed = claimed.into_inner().unwrap();
+        match first_error.into_inner().unwrap() {
+            Some(e) if claimed == 0 => Err(e),
+            _ => Ok(claimed),
         }
-        Ok(())
     }
 
     /// **@param award_type**: `"medal"` or `""`
@@ -369,59 +653,43 @@ impl Client {
         award_type: impl AsRef<str>,
         fuben_id: f64
     ) -> Result<f64> {
-        let res = self.send_amf(
+        #[derive(Deserialize)]
+        struct FubenAward {
+            next: f64,
+        }
+
+        let FubenAward { next } = self.call(
             "api.fuben.award",
-            "/1",
-            array(vec![string(award_type.as_ref()), number(fuben_id)]),
+            (award_type.as_ref(), fuben_id),
         ).await?;
 
-        let Body { data, .. } = res.bodies
-            .first()
-            .ok_or("response packet body is empty.")?;
-
-        let data = data.try_as_amf0_object().ok_or(ERR_PARSE_AMF_OBJ)?;
-
-        match data.get("next") {
-            Some(n) => n.try_as_f64().ok_or("无法将`next`解析为数字".into()),
-            None => get_error_from_map(&data, "未知错误：返回数据中无`next`".into())
-        }
-
+        Ok(next)
     }
 
+    /// **@param fuben_id**: usually a number, see `reset_fuben_reward`
+    /// 
+    /// **@return** (integral, medals)
     pub async fn get_fuben_reward(
         &self,
-        fuben_id: impl Into<Amf0Value>,
+        fuben_id: impl Serialize,
     ) -> Result<(usize, usize)> {
-        let fuben_id: Amf0Value = fuben_id.into();
-        let res = self.send_amf(
-            "api.fuben.reward",
-            "/1",
-            array(vec![fuben_id]),
-        ).await?;
-
-        if res.bodies.len() == 0 {
-            Err("response packet body is empty.")?;
+        #[derive(Deserialize)]
+        struct FubenReward {
+            integral: usize,
+            medal: Medal,
         }
-        let ref data = res.bodies[0].data;
-
-        let data = data.try_as_amf0_object().ok_or(ERR_PARSE_AMF_OBJ)?;
-    
-        let integral = data.get("integral")
-            .ok_or("未知错误：返回数据中无`integral`")?
-            .try_as_number()
-            .ok_or("无法将`integral`字段解析为Number")?;
 
-        let medal = data.get("medal")
-            .ok_or("返回数据中无`medal`字段")?
-            .try_as_amf0_object()
-            .ok_or("无法将`medal`字段解析为Object")?
-            .get("amount")
-            .ok_or("Object`medal`中没有`amount`")?
-            .try_as_number()
-            .ok_or("无法将``字段解析为Number")?;
+        #[derive(Deserialize)]
+        struct Medal {
+            amount: usize,
+        }
 
-        Ok((integral as usize, medal as usize))
+        let FubenReward { integral, medal } = self.call(
+            "api.fuben.reward",
+            (fuben_id,),
+        ).await?;
 
+        Ok((integral, medal.amount))
     }
 
     pub async fn reset_fuben_reward(
@@ -429,7 +697,7 @@ impl Client {
         fuben_id: f64,
     ) -> Result<()> {
         let fuben_id = format!("{}.9999999999111", fuben_id);
-        self.get_fuben_reward(string(fuben_id)).await?;
+        self.get_fuben_reward(fuben_id).await?;
         Ok(())
     }
 
@@ -437,23 +705,28 @@ impl Client {
         &self,
         fuben_id: f64,
         times: usize,
+        cancel: &CancellationToken,
     ) -> Result<()> {
-        let (_, medal) = self.get_fuben_reward(number(fuben_id)).await?;
-        println!("--- current medals: {}", medal);
-        for i in 0..times {
-            if i != 0 {
-                wait_a_moment().await;
+        let (_, medal) = self.get_fuben_reward(fuben_id).await?;
+        self.emit(Event::Progress(Progress::FubenMedals { fuben_id, medals: medal }));
+        'outer: for i in 1..=times {
+            if self.loop_step(cancel, || self.reset_fuben_reward(fuben_id)).await?.is_none() {
+                break;
             }
-            self.reset_fuben_reward(fuben_id).await?;
-            print!("No.{:-3} : reset", i);
-            std::io::stdout().flush().map_err(|e| format!("fail to flush stdout: {}", e))?;
+            self.emit(Event::Progress(Progress::FubenReset { fuben_id, round: i }));
             for j in 1.. {
-                wait_a_moment().await;
-                let next = self.get_fuben_award("medal", fuben_id).await?;
-                print!(" : get-{}", j);
-                std::io::stdout().flush().map_err(|e| format!("fail to flush stdout: {}", e))?;
-                if next == 0. || next > medal as f64 {
-                    println!(" : ok");
+                let next = match self.loop_step(cancel, || self.get_fuben_award("medal", fuben_id)).await? {
+                    Some(next) => next,
+                    None => break 'outer,
+                };
+                let done = next == 0. || next > medal as f64;
+                self.emit(Event::Progress(Progress::FubenAward {
+                    fuben_id,
+                    round: i,
+                    attempt: j,
+                    done,
+                }));
+                if done {
                     break;
                 }
             }
@@ -462,10 +735,9 @@ impl Client {
     }
 
     pub(crate) async fn get_reward(&self, awards_key: &str) -> Result<()> {
-        self.send_amf(
+        let _: IgnoredAny = self.call(
             "api.reward.lottery",
-            "/1",
-            array(vec![string(awards_key)]),
+            (awards_key,),
         ).await?;
         Ok(())
     }
@@ -476,35 +748,23 @@ impl Client {
         challenge_id: f64,
         plant_ids: impl Iterator<Item = f64>,
     ) -> Result<bool> {
+        #[derive(Deserialize)]
+        struct Challenge {
+            is_winning: bool,
+            awards_key: String,
+        }
+
         let target_uri = ChallengeType::get_amf_target(&challenge_type);
-        let plant_ids = plant_ids.map(number).collect();
-        let res = self.send_amf(
+        let plant_ids: Vec<f64> = plant_ids.collect();
+
+        let Challenge { is_winning, awards_key } = self.call(
             target_uri,
-            "/1",
-            array(vec![number(challenge_id), array(plant_ids)]),
+            (challenge_id, plant_ids),
         ).await?;
 
-        let Body { data, .. } = res.bodies
-            .first()
-            .ok_or("response packet body is empty.")?;
-
-        let data = data.try_as_amf0_object().ok_or(ERR_PARSE_AMF_OBJ)?;
-
-        let win = data.get("is_winning");
-
-        if let Some(win) = win {
-            win.try_as_bool().ok_or("无法将`is_winning`解析为bool")?;
-
-            let awards_key = data.get("awards_key")
-                .ok_or("返回数据中无`awards_key`")?
-                .try_as_str()
-                .ok_or("无法将`awards_key`解析为&str")?;
-
-            self.get_reward(awards_key).await?;
-        }
-
-        get_error_from_map(&data, "返回数据中无`is_winning`".into())
+        self.get_reward(&awards_key).await?;
 
+        Ok(is_winning)
     }
 
     pub async fn challenge_fuben_repeat(
@@ -512,16 +772,21 @@ impl Client {
         fuben_id: f64,
         plant_ids: Vec<f64>,
         times: usize,
+        cancel: &CancellationToken,
     ) -> Result<()> {
         use ChallengeType::*;
 
         for i in 1..=times {
-            if i != 1 {
-                wait_a_moment().await;
-            }
-            let plant_ids = plant_ids.iter().map(ToOwned::to_owned);
-            let win = self.challenge(Fuben, fuben_id, plant_ids).await?;
-            println!("repeat {:-3} : win={}", i, win);
+            let challenge = || self.challenge(Fuben, fuben_id, plant_ids.iter().copied());
+            let win = match self.loop_step(cancel, challenge).await? {
+                Some(win) => win,
+                None => break,
+            };
+            self.emit(Event::Progress(Progress::Challenged {
+                fuben_id,
+                round: i,
+                win,
+            }));
         }
         Ok(())
     }
@@ -529,82 +794,282 @@ impl Client {
 
 }
 
-fn get_error_from_map<T>(data: &HashMap<&str,&Amf0Value>, or: ErrorKind) -> Result<T> {
-    let error = data.get("desctiption").and_then(|e| e.try_as_str());
+/// the result of a call, or the error if the call failed.
+pub(crate) fn response_data(response: Option<Response>, response_uri: &str) -> Result<Value> {
+    match response {
+        Some(Response::Result(data)) => Ok(data),
+        Some(Response::Status(data)) => Err(RemoteFault::from_status(&data).into()),
+        None => Err(ErrorKind::protocol(format!("response packet has no body for `{}`", response_uri), None)),
+    }
+}
 
-    Err(if error.is_some() {
-        let error = error.unwrap();
-        if error.is_ascii() {
-            or // 替换为更友好的错误信息
-        } else {
-            error.to_owned().into()
-        }
-    } else {
-        or
+/// deserialize the result of a call, or turn it into an error.
+pub(crate) fn decode_response<R: DeserializeOwned>(
+    response: Option<Response>,
+    response_uri: &str,
+) -> Result<R> {
+    let data = response_data(response, response_uri)?;
+
+    amf::from_value(&data).or_else(|e| {
+        get_error(&data, ErrorKind::protocol("无法解析返回的数据", e))
+    })
+}
+
+/// the game rejects a call by returning `{ description, code }` as the result.
+fn get_error<T>(data: &Value, or: ErrorKind) -> Result<T> {
+    #[derive(Deserialize)]
+    struct Rejected {
+        description: String,
+        #[serde(default)]
+        code: Option<String>,
+    }
+
+    Err(match amf::from_value::<Rejected>(data) {
+        Ok(r) => ErrorKind::rejected(r.description, r.code),
+        Err(_) => or,
     })
 }
 
+/// the timestamp of the game data files and the php pages.
+fn unix_millis() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis()
+}
+
+/// the user agent and extra headers of `http`, which are sent with every request.
+fn extra_headers(http: &HttpOptions) -> Result<header::HeaderMap> {
+    use header::{HeaderName, HeaderValue};
+
+    let invalid = |name: &str, e: &dyn std::fmt::Display| ErrorKind::Config(format!("invalid header `{}`: {}", name, e));
+    let mut headers = header::HeaderMap::new();
+    if let Some(user_agent) = &http.user_agent {
+        headers.insert(header::USER_AGENT, HeaderValue::from_str(user_agent)
+            .map_err(|e| invalid("user-agent", &e))?);
+    }
+    for (name, value) in &http.headers {
+        let header_name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| invalid(name, &e))?;
+        let value = HeaderValue::from_str(value).map_err(|e| invalid(name, &e))?;
+        headers.insert(header_name, value);
+    }
+    Ok(headers)
+}
+
 pub struct ClientBuilder {
     server: Option<u8>,
+    server_url: Option<Url>,
+    servers: ServerRegistry,
+    http: HttpOptions,
     cookies: HashMap<String, String>,
+    transport: Option<Arc<dyn AmfTransport>>,
+    pacing: Pacing,
+    retry: RetryPolicy,
+    on_event: Option<EventHandler>,
+    reauth: Option<Reauth>,
+    cancel: CancellationToken,
+    sys_cache: Option<SysCache>,
+    sig_key: Option<String>,
+    account: Option<AccountInfo>,
 }
 
 impl ClientBuilder {
     pub(crate) fn new () -> Self {
         ClientBuilder {
             server: None,
+            server_url: None,
+            servers: ServerRegistry::new(),
+            http: HttpOptions::default(),
             cookies: HashMap::new(),
+            transport: None,
+            pacing: Pacing::default(),
+            retry: RetryPolicy::default(),
+            on_event: None,
+            reauth: None,
+            cancel: CancellationToken::new(),
+            sys_cache: None,
+            sig_key: None,
+            account: None,
         }
     }
 
     pub fn build(self) -> Result<Client> {
-        use header::{REFERER,};
-
-        let server = self.server.ok_or("您必须给定登录的服务器")?;
-
-        let server_url = Client::resolve_server(server);
-        let referer = server_url.join("main.swf").unwrap();
-
-        let reqwest_client = 
-            reqwest::Client::builder()
-                .default_headers({
-                    let mut headers = header::HeaderMap::new();
-                    headers.append(REFERER, referer.as_str().parse().unwrap());
-                    headers
-                })
-                .build()
-                .map_err(|e|{
-                    format!("internal reqwest client building error: {}", e)
-                })?;
-
-        let cookies = {
-            let total_bytes = self.cookies
-                .iter()
-                .map(|(k,v)| k.len() + v.len() + "=".len() + ";".len())
-                .sum::<usize>();
-            let mut cookies = String::with_capacity(total_bytes);
-            for (key, val) in self.cookies {
-                cookies.push_str(&key);
-                cookies.push('=');
-                cookies.push_str(&val);
-                cookies.push(';');
-            }
-            cookies
+        let mut server = match (self.server, self.server_url) {
+            (Some(id), url) => {
+                let mut server = self.servers.resolve(id);
+                if let Some(url) = url {
+                    server.base_url = url;
+                }
+                server
+            },
+            (None, Some(url)) => Server::new(0, url),
+            (None, None) => return Err(ErrorKind::Config("您必须给定登录的服务器".to_owned())),
+        };
+        if let Some(flash_version) = &self.http.flash_version {
+            server.flash_version = flash_version.clone();
+        }
+        let amf_url = server.amf_url()?;
+        let headers = extra_headers(&self.http)?;
+
+        let transport = match self.transport {
+            Some(transport) => transport,
+            None => Arc::new(ReqwestTransport::with_options(&self.http)?),
+        };
+
+        let pacer = Pacer::new(self.pacing, &server.base_url);
+        let account = AccountInfo {
+            cookies: HashMap::new(),
+            ..self.account.unwrap_or_else(|| AccountInfo {
+                server: self.server.unwrap_or(0),
+                ..Default::default()
+            })
         };
 
         Ok(Client {
-            reqwest_client,
+            transport,
+            pacer,
+            retry: self.retry,
+            on_event: self.on_event,
+            subscribers: Mutex::new(Vec::new()),
+            cancel: self.cancel,
+            reauth: self.reauth,
             server,
-            server_url,
-            cookies,
+            amf_url,
+            headers,
+            cookies: Mutex::new(self.cookies.into_iter().collect()),
+            sys_cache: self.sys_cache,
+            sig_key: self.sig_key,
+            user: Mutex::new(None),
+            account,
         })
     }
 
-    #[allow(unused_mut)]
     pub fn account(mut self, account: AccountInfo) -> Self {
-        let AccountInfo { server, cookies} = account;
-        self.server(server as u8)
-            .cookies(cookies.into_iter())
+        self.account = Some(account.clone());
+        let AccountInfo { server, cookies, base_url, servers, http, sig_key } = account;
+        self.servers.extend(servers);
+        self.http = http;
+        self.sig_key = sig_key;
+        // the base url replaces the server, the id is only kept in the account.
+        self = match base_url {
+            Some(url) => self.server_url(url),
+            None => self.server(server),
+        };
+        self.cookies(cookies.into_iter())
+    }
+
+    /// set all the http options at once, see [`HttpOptions`].
+    pub fn http(mut self, http: HttpOptions) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.http.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// the `x-flash-version` header, instead of the one of the server.
+    pub fn flash_version(mut self, flash_version: impl Into<String>) -> Self {
+        self.http.flash_version = Some(flash_version.into());
+        self
+    }
+
+    /// send the header with every request, it replaces the default one with the same name.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.http.headers.insert(name.into(), value.into());
+        self
+    }
+
+    /// send requests through an `http://`, `https://` or `socks5://` (with the `socks` feature) proxy.
+    ///
+    /// like the timeouts and keep-alive, it is ignored if the transport is set by [`ClientBuilder::transport`].
+    pub fn proxy(mut self, proxy: Url) -> Self {
+        self.http.proxy = Some(proxy);
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.http.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// the timeout of a whole request, including reading the response.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.http.timeout = Some(timeout);
+        self
+    }
+
+    /// whether connections are reused, `true` by default.
+    pub fn keep_alive(mut self, keep_alive: bool) -> Self {
+        self.http.keep_alive = Some(keep_alive);
+        self
+    }
+
+    /// send requests with `transport` instead of the default [`ReqwestTransport`].
+    pub fn transport(mut self, transport: impl AmfTransport + 'static) -> Self {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// send requests to `url` instead of the base url of the server,
+    /// e.g. a mirror domain or a local gateway for testing.
+    pub fn server_url(mut self, url: Url) -> Self {
+        self.server_url = Some(url);
+        self
+    }
+
+    /// add the servers to the registry which server ids are resolved with, see [`ServerRegistry`].
+    pub fn servers(mut self, servers: impl IntoIterator<Item = Server>) -> Self {
+        self.servers.extend(servers);
+        self
+    }
+
+    /// how fast requests are sent, 800~1400ms between two requests by default.
+    pub fn pacing(mut self, pacing: Pacing) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    /// how requests failed for transient reasons are retried, 3 attempts by default.
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// handle the events of the client, see [`Event`].
+    pub fn on_event(mut self, on_event: impl Fn(&Event) + Send + Sync + 'static) -> Self {
+        self.on_event = Some(Arc::new(on_event));
+        self
+    }
+
+    /// refresh the cookies with `reauth` when the session is expired,
+    /// the returned cookies are added to the client and the interrupted request is sent once more.
+    pub fn reauth<F, Fut>(mut self, reauth: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<HashMap<String, String>>> + Send + 'static,
+    {
+        self.reauth = Some(session::reauth(reauth));
+        self
+    }
+
+    /// stop all the loops (e.g. [`Client::quality_up_to`]) of the client when `token` is cancelled,
+    /// the request in flight is finished first. The client can not run loops any more after it,
+    /// to stop a single loop, cancel the token passed to the loop instead.
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// keep the game data files on disk, so they are not downloaded on every start, see [`SysCache`].
+    pub fn sys_cache(mut self, cache: SysCache) -> Self {
+        self.sys_cache = Some(cache);
+        self
+    }
+
+    /// the key of the game client which signs the php pages (e.g. the warehouse),
+    /// the sig is the md5 of the key followed by the timestamp in milliseconds.
+    pub fn sig_key(mut self, key: impl Into<String>) -> Self {
+        self.sig_key = Some(key.into());
+        self
     }
 
     pub fn server(mut self, server: u8) -> Self{
@@ -625,9 +1090,3 @@ impl ClientBuilder {
         self
     }
 }
-
-pub async fn wait_a_moment() -> () {
-    let duration: u64 = rand::thread_rng()
-        .gen_range(800..1400);
-    tokio::time::sleep(Duration::from_millis(duration)).await;
-}
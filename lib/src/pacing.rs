@@ -0,0 +1,226 @@
+//! How fast a `Client` sends requests.
+//!
+//! Every request waits for:
+//! - a random interval since the previous request of the same client, see [`Pacing::interval`]
+//! - a token from the bucket of the client, see [`Pacing::rate`]
+//! - a token from the bucket shared by all clients of the same server, see [`Pacing::server_rate`]
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use once_cell::sync::Lazy;
+use rand::Rng;
+use reqwest::Url;
+use tokio::time::{sleep_until, Instant};
+
+/// The pacing policy of a `Client`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pacing {
+    /// the interval between two requests is a random duration in `min..=max`.
+    pub interval: Option<(Duration, Duration)>,
+    /// limit of the client.
+    pub rate: Option<Rate>,
+    /// limit shared by all clients of the same server in this process,
+    /// the first client of a server decides the limit.
+    pub server_rate: Option<Rate>,
+}
+
+/// A token bucket which holds at most `burst` tokens, and gets `per_second` tokens every second.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+impl Rate {
+    pub fn per_second(per_second: f64) -> Self {
+        Rate {
+            per_second,
+            burst: 1,
+        }
+    }
+
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+}
+
+/// The same as the old `wait_a_moment`, 800~1400ms between two requests.
+impl Default for Pacing {
+    fn default() -> Self {
+        Pacing {
+            interval: Some((Duration::from_millis(800), Duration::from_millis(1400))),
+            rate: None,
+            server_rate: None,
+        }
+    }
+}
+
+impl Pacing {
+    /// send requests as fast as possible, e.g. for tests.
+    pub fn unlimited() -> Self {
+        Pacing {
+            interval: None,
+            rate: None,
+            server_rate: None,
+        }
+    }
+
+    pub fn interval(mut self, min: Duration, max: Duration) -> Self {
+        self.interval = Some((min, max.max(min)));
+        self
+    }
+
+    pub fn rate(mut self, rate: Rate) -> Self {
+        self.rate = Some(rate);
+        self
+    }
+
+    pub fn server_rate(mut self, rate: Rate) -> Self {
+        self.server_rate = Some(rate);
+        self
+    }
+}
+
+/// Buckets shared by the clients of the same server.
+static SERVER_BUCKETS: Lazy<Mutex<HashMap<String, Arc<TokenBucket>>>> = Lazy::new(Default::default);
+
+pub(crate) struct Pacer {
+    interval: Option<(Duration, Duration)>,
+    next: Mutex<Option<Instant>>,
+    bucket: Option<TokenBucket>,
+    server_bucket: Option<Arc<TokenBucket>>,
+}
+
+impl Pacer {
+    pub(crate) fn new(pacing: Pacing, server_url: &Url) -> Self {
+        let server_bucket = pacing.server_rate.map(|rate| {
+            SERVER_BUCKETS
+                .lock()
+                .unwrap()
+                .entry(server_url.origin().ascii_serialization())
+                .or_insert_with(|| Arc::new(TokenBucket::new(rate)))
+                .clone()
+        });
+        Pacer {
+            interval: pacing.interval,
+            next: Mutex::new(None),
+            bucket: pacing.rate.map(TokenBucket::new),
+            server_bucket,
+        }
+    }
+
+    /// wait until the next request can be sent.
+    pub(crate) async fn wait(&self) {
+        let mut until = Instant::now();
+        if let Some((min, max)) = self.interval {
+            let mut next = self.next.lock().unwrap();
+            if let Some(next) = *next {
+                until = until.max(next);
+            }
+            let interval = if min < max {
+                rand::thread_rng().gen_range(min..=max)
+            } else {
+                min
+            };
+            *next = Some(until + interval);
+        }
+        if let Some(bucket) = &self.bucket {
+            until = until.max(bucket.reserve());
+        }
+        if let Some(bucket) = &self.server_bucket {
+            until = until.max(bucket.reserve());
+        }
+        sleep_until(until).await;
+    }
+}
+
+struct TokenBucket {
+    rate: Rate,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    fn new(rate: Rate) -> Self {
+        TokenBucket {
+            rate,
+            state: Mutex::new((rate.burst as f64, Instant::now())),
+        }
+    }
+
+    /// take a token, and return when it is available.
+    ///
+    /// tokens can be borrowed from the future, so waiters are served in order.
+    fn reserve(&self) -> Instant {
+        let mut state = self.state.lock().unwrap();
+        let (tokens, last) = &mut *state;
+        let now = Instant::now();
+        let burst = self.rate.burst as f64;
+        *tokens = (*tokens + (now - *last).as_secs_f64() * self.rate.per_second).min(burst);
+        *last = now;
+        *tokens -= 1.;
+        if *tokens >= 0. {
+            now
+        } else {
+            now + Duration::from_secs_f64(-*tokens / self.rate.per_second)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pacing() {
+        let url = Url::parse("http://pacing.test/").unwrap();
+
+        let pacer = Pacer::new(Pacing::unlimited(), &url);
+        let start = Instant::now();
+        for _ in 0..10 {
+            pacer.wait().await;
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        // the first request is not delayed
+        let pacer = Pacer::new(Pacing::unlimited().interval(Duration::from_millis(100), Duration::from_millis(200)), &url);
+        let start = Instant::now();
+        pacer.wait().await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        pacer.wait().await;
+        pacer.wait().await;
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert!(start.elapsed() <= Duration::from_millis(400));
+
+        // 2 tokens at once, then 1 token every 500ms
+        let pacer = Pacer::new(Pacing::unlimited().rate(Rate::per_second(2.).burst(2)), &url);
+        let start = Instant::now();
+        pacer.wait().await;
+        pacer.wait().await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        pacer.wait().await;
+        pacer.wait().await;
+        assert_eq!(start.elapsed(), Duration::from_millis(1000));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_server_rate() {
+        let url = Url::parse("http://server-rate.test/").unwrap();
+        let pacing = Pacing::unlimited().server_rate(Rate::per_second(1.));
+        let a = Pacer::new(pacing.clone(), &url);
+        let b = Pacer::new(pacing.clone(), &url);
+        let other = Pacer::new(pacing, &Url::parse("http://other-server.test/").unwrap());
+
+        let start = Instant::now();
+        a.wait().await;
+        other.wait().await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        b.wait().await;
+        a.wait().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+    }
+}
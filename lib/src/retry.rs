@@ -0,0 +1,123 @@
+//! Retry requests which fail for transient reasons.
+//!
+//! Most calls of the game are not idempotent, e.g. a quality up spends a book, and a request
+//! which failed after it is sent may have reached the server already. So only the requests
+//! which are never sent ([`ErrorKind::is_unsent`]) are retried by default, the calls which are
+//! safe to repeat can opt in to retry all [transient](ErrorKind::is_transient) failures
+//! with [`Client::call_idempotent`](crate::Client::call_idempotent).
+
+use std::time::Duration;
+
+use crate::{amf::packet::PacketError, ErrorKind};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// the max number of attempts, including the first one.
+    pub attempts: u32,
+    /// the delay before the first retry.
+    pub initial_backoff: Duration,
+    /// the delay is multiplied by `multiplier` after each retry, up to `max_backoff`.
+    pub multiplier: f64,
+    pub max_backoff: Duration,
+}
+
+/// 3 attempts, wait 1s and then 2s.
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 3,
+            initial_backoff: Duration::from_secs(1),
+            multiplier: 2.,
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// fail on the first error.
+    pub fn never() -> Self {
+        RetryPolicy {
+            attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    pub fn attempts(mut self, attempts: u32) -> Self {
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    /// the delay before the retry after `attempt` (starting from 1) fails,
+    /// or `None` if there should be no more attempts.
+    pub fn backoff_after(&self, attempt: u32) -> Option<Duration> {
+        if attempt >= self.attempts {
+            return None;
+        }
+        let delay = self.initial_backoff.as_secs_f64() * self.multiplier.powi(attempt as i32 - 1);
+        Some(Duration::from_secs_f64(delay.min(self.max_backoff.as_secs_f64())))
+    }
+}
+
+impl ErrorKind {
+    /// whether the request may succeed if it is sent again:
+    /// network errors, 5xx/408/429 status, and empty or truncated responses.
+    ///
+    /// game errors and faults are permanent.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ErrorKind::Connect(_) | ErrorKind::Network(_) => true,
+            ErrorKind::HttpStatus(status) => {
+                status.is_server_error() || status.as_u16() == 408 || status.as_u16() == 429
+            },
+            ErrorKind::EmptyResponse => true,
+            ErrorKind::Packet(PacketError::UnexpectedEof { .. }) => true,
+            _ => false,
+        }
+    }
+
+    /// whether the request failed before it is sent, so sending it again can not do anything twice.
+    pub fn is_unsent(&self) -> bool {
+        matches!(self, ErrorKind::Connect(_))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::default().attempts(5).backoff(Duration::from_secs(1), Duration::from_secs(5));
+        let delays: Vec<_> = (1..=5).map(|i| policy.backoff_after(i)).collect();
+        assert_eq!(delays, [
+            Some(Duration::from_secs(1)),
+            Some(Duration::from_secs(2)),
+            Some(Duration::from_secs(4)),
+            Some(Duration::from_secs(5)),
+            None,
+        ]);
+        assert_eq!(RetryPolicy::never().backoff_after(1), None);
+    }
+
+    #[test]
+    fn test_is_transient() {
+        use reqwest::StatusCode;
+
+        assert!(ErrorKind::Connect("connection refused".into()).is_unsent());
+        assert!(ErrorKind::Network("connection reset".into()).is_transient());
+        assert!(!ErrorKind::Network("connection reset".into()).is_unsent());
+        assert!(!ErrorKind::HttpStatus(StatusCode::BAD_GATEWAY).is_unsent());
+        assert!(ErrorKind::HttpStatus(StatusCode::BAD_GATEWAY).is_transient());
+        assert!(ErrorKind::HttpStatus(StatusCode::TOO_MANY_REQUESTS).is_transient());
+        assert!(ErrorKind::EmptyResponse.is_transient());
+        assert!(!ErrorKind::HttpStatus(StatusCode::NOT_FOUND).is_transient());
+        assert!(!ErrorKind::Fault(Default::default()).is_transient());
+        assert!(!ErrorKind::rejected("今日次数已用完".to_owned(), None).is_transient());
+    }
+}
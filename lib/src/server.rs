@@ -0,0 +1,219 @@
+//! The game servers, and where their amf gateways are.
+//!
+//! Servers which are not in the registry are resolved by the naming rule of youkia,
+//! e.g. `http://pvz-s1.youkia.com`. Merged servers, mirror domains or a local stand-in
+//! can be added in the account config:
+//!
+//! ```toml
+//! server = 90
+//!
+//! [[servers]]
+//! id = 90
+//! name = "合区"
+//! base_url = "https://s90.example.com"
+//! amf_path = "/pvz/amf/"
+//! xml_path = "/pvz/php_xml/"
+//! index_path = "/pvz/index.php/"
+//! flash_version = "34,0,0,192"
+//! ```
+
+use std::{collections::BTreeMap, path::Path};
+
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::{ErrorKind, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Server {
+    pub id: u8,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    pub base_url: Url,
+    /// the path of the amf gateway, relative to `base_url`.
+    #[serde(default = "default_amf_path")]
+    pub amf_path: String,
+    /// the directory of the game data files (`tool.xml`, ...), relative to `base_url`.
+    #[serde(default = "default_xml_path")]
+    pub xml_path: String,
+    /// the entry of the php pages (`Warehouse/index`, ...), relative to `base_url`.
+    #[serde(default = "default_index_path")]
+    pub index_path: String,
+    /// sent as the `x-flash-version` header.
+    #[serde(default = "default_flash_version")]
+    pub flash_version: String,
+}
+
+fn default_amf_path() -> String {
+    "/pvz/amf/".to_owned()
+}
+
+fn default_xml_path() -> String {
+    "/pvz/php_xml/".to_owned()
+}
+
+fn default_index_path() -> String {
+    "/pvz/index.php/".to_owned()
+}
+
+fn default_flash_version() -> String {
+    "34,0,0,192".to_owned()
+}
+
+impl Server {
+    pub fn new(id: u8, base_url: Url) -> Self {
+        Server {
+            id,
+            name: String::new(),
+            base_url,
+            amf_path: default_amf_path(),
+            xml_path: default_xml_path(),
+            index_path: default_index_path(),
+            flash_version: default_flash_version(),
+        }
+    }
+
+    /// the url of the game data file `name`, e.g. `tool.xml`.
+    pub fn xml_url(&self, name: &str) -> Result<Url> {
+        self.base_url
+            .join(&self.xml_path)
+            .and_then(|dir| dir.join(name))
+            .map_err(|e| ErrorKind::Config(format!("invalid xml path `{}`: {}", self.xml_path, e)))
+    }
+
+    /// the url of the php page `page`, e.g. `Warehouse/index/sig/{sig}`.
+    pub fn page_url(&self, page: &str) -> Result<Url> {
+        self.base_url
+            .join(&self.index_path)
+            .and_then(|dir| dir.join(page))
+            .map_err(|e| ErrorKind::Config(format!("invalid index path `{}`: {}", self.index_path, e)))
+    }
+
+    /// the server `id` of youkia, `pvz-s{id}.youkia.com` below 12 and `s{id}.youkia.pvz.youkia.com` above.
+    pub fn youkia(id: u8) -> Self {
+        let url = if id < 12 {
+            format!("http://pvz-s{}.youkia.com", id)
+        } else {
+            format!("http://s{}.youkia.pvz.youkia.com", id)
+        };
+        Server::new(id, Url::parse(&url).expect("fail to parse server host!"))
+    }
+
+    pub fn amf_url(&self) -> Result<Url> {
+        self.base_url
+            .join(&self.amf_path)
+            .map_err(|e| ErrorKind::Config(format!("invalid amf path `{}`: {}", self.amf_path, e)))
+    }
+}
+
+/// The known servers, by id.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ServerRegistry {
+    #[serde(default)]
+    servers: Vec<Server>,
+    #[serde(skip)]
+    index: BTreeMap<u8, usize>,
+}
+
+impl ServerRegistry {
+    pub fn new() -> Self {
+        ServerRegistry::default()
+    }
+
+    /// read the `[[servers]]` tables of a toml file.
+    pub async fn from_file(file_name: impl AsRef<Path>) -> Result<ServerRegistry> {
+        let file_content = tokio::fs::read(file_name).await?;
+        ServerRegistry::from_bytes(file_content)
+    }
+
+    pub fn from_bytes<T: AsRef<[u8]>>(bytes: T) -> Result<ServerRegistry> {
+        let registry: ServerRegistry = toml::from_slice(bytes.as_ref())
+            .map_err(|e| ErrorKind::Config(format!("invalid server list: {}", e)))?;
+        Ok(registry.servers.into_iter().collect())
+    }
+
+    /// add or replace the server with the same id, return the old one.
+    pub fn insert(&mut self, server: Server) -> Option<Server> {
+        match self.index.get(&server.id) {
+            Some(&i) => Some(std::mem::replace(&mut self.servers[i], server)),
+            None => {
+                self.index.insert(server.id, self.servers.len());
+                self.servers.push(server);
+                None
+            },
+        }
+    }
+
+    pub fn get(&self, id: u8) -> Option<&Server> {
+        self.index.get(&id).map(|&i| &self.servers[i])
+    }
+
+    /// the server `id` in the registry, or the one of youkia.
+    pub fn resolve(&self, id: u8) -> Server {
+        self.get(id).cloned().unwrap_or_else(|| Server::youkia(id))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Server> {
+        self.servers.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.servers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.servers.is_empty()
+    }
+}
+
+impl FromIterator<Server> for ServerRegistry {
+    fn from_iter<I: IntoIterator<Item = Server>>(iter: I) -> Self {
+        let mut registry = ServerRegistry::new();
+        registry.extend(iter);
+        registry
+    }
+}
+
+impl Extend<Server> for ServerRegistry {
+    fn extend<I: IntoIterator<Item = Server>>(&mut self, iter: I) {
+        for server in iter {
+            self.insert(server);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resolve() -> Result<()> {
+        let registry = ServerRegistry::from_bytes(r#"
+            [[servers]]
+            id = 90
+            name = "合区"
+            base_url = "https://mirror.example.com/game/"
+
+            [[servers]]
+            id = 1
+            base_url = "http://127.0.0.1:8080"
+            amf_path = "amf.php"
+            flash_version = "32,0,0,100"
+        "#)?;
+        assert_eq!(registry.len(), 2);
+
+        let merged = registry.resolve(90);
+        assert_eq!(merged.name, "合区");
+        assert_eq!(merged.amf_url()?.as_str(), "https://mirror.example.com/pvz/amf/");
+        assert_eq!(merged.flash_version, "34,0,0,192");
+
+        let local = registry.resolve(1);
+        assert_eq!(local.amf_url()?.as_str(), "http://127.0.0.1:8080/amf.php");
+        assert_eq!(local.xml_url("tool.xml")?.as_str(), "http://127.0.0.1:8080/pvz/php_xml/tool.xml");
+        assert_eq!(local.page_url("Warehouse/index")?.as_str(), "http://127.0.0.1:8080/pvz/index.php/Warehouse/index");
+
+        assert_eq!(registry.resolve(3).base_url.as_str(), "http://pvz-s3.youkia.com/");
+        assert_eq!(registry.resolve(20).base_url.as_str(), "http://s20.youkia.pvz.youkia.com/");
+        Ok(())
+    }
+}
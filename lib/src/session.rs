@@ -0,0 +1,134 @@
+//! Detect expired sessions, see [`ErrorKind::SessionExpired`](crate::ErrorKind::SessionExpired).
+//!
+//! The gateway does not say so explicitly, a session is considered expired when:
+//! - the gateway redirects (3xx) or responds with `401`, usually to the login page
+//! - the gateway responds with an html page which sends the browser to the login page
+//! - a call fails with one of the faults of an expired session, e.g. `请重新登录`
+//!
+//! Other html pages and faults, e.g. `登录奖励已领取`, are not about the session.
+
+use std::{collections::HashMap, future::Future, sync::Arc};
+
+use reqwest::header::LOCATION;
+
+use crate::{
+    amf::packet::{Packet, RemoteFault},
+    transport::{AmfResponse, BoxFuture},
+    Result,
+};
+
+/// The fault strings of an expired session, the whole (trimmed, case-insensitive) fault string is matched.
+const EXPIRED_FAULTS: [&str; 7] = [
+    "请重新登录",
+    "请先登录",
+    "登录已失效, 请重新登录",
+    "登录超时, 请重新登录",
+    "会话已过期",
+    "session expired",
+    "not logged in",
+];
+
+/// The path of the login page, which an html page of an expired session links to.
+const LOGIN_PATH: &str = "/login";
+
+/// Refresh the cookies of an expired session, e.g. log in again,
+/// the returned cookies are added to the client.
+pub(crate) type Reauth = Arc<dyn Fn() -> BoxFuture<'static, Result<HashMap<String, String>>> + Send + Sync>;
+
+pub(crate) fn reauth<F, Fut>(f: F) -> Reauth
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<HashMap<String, String>>> + Send + 'static,
+{
+    Arc::new(move || Box::pin(f()))
+}
+
+/// check the http response before it is parsed, return the reason if the session is expired.
+pub(crate) fn check_response(resp: &AmfResponse) -> Option<String> {
+    if resp.status.is_redirection() {
+        let location = resp.headers
+            .get(LOCATION)
+            .and_then(|l| l.to_str().ok())
+            .unwrap_or_default();
+        return Some(format!("redirected to `{}`", location));
+    }
+    if resp.status.as_u16() == 401 {
+        return Some("unauthorized".to_owned());
+    }
+    let is_html = matches!(resp.body.iter().find(|b| !b.is_ascii_whitespace()), Some(b'<'));
+    let to_login = resp.body
+        .windows(LOGIN_PATH.len())
+        .any(|w| w.eq_ignore_ascii_case(LOGIN_PATH.as_bytes()));
+    if resp.status.is_success() && is_html && to_login {
+        return Some("the gateway responded with an html page to the login page".to_owned());
+    }
+    None
+}
+
+/// check the faults in the response packet, return the reason if the session is expired.
+pub(crate) fn check_packet(packet: &Packet) -> Option<String> {
+    packet.bodies
+        .iter()
+        .filter(|b| b.target_uri.ends_with("/onStatus"))
+        .map(|b| RemoteFault::from_status(&b.data))
+        .find(|fault| {
+            let fault_string = fault.fault_string.trim();
+            EXPIRED_FAULTS.iter().any(|f| f.eq_ignore_ascii_case(fault_string))
+        })
+        .map(|fault| fault.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amf::{amf0::{object, string}, Version};
+
+    #[test]
+    fn test_check_response() {
+        let mut resp = AmfResponse::ok(&b"\r\n<!DOCTYPE html><html><script>location.href='/pvz/index.php/default/login'</script>"[..]);
+        assert!(check_response(&resp).is_some());
+
+        // an html page which is not about the login, e.g. an error page of a proxy
+        let html = AmfResponse::ok(&b"<html><body>503 Service Unavailable</body></html>"[..]);
+        assert_eq!(check_response(&html), None);
+
+        resp.status = reqwest::StatusCode::BAD_GATEWAY;
+        assert_eq!(check_response(&resp), None);
+
+        resp.status = reqwest::StatusCode::FOUND;
+        resp.headers.insert(LOCATION, "/pvz/index.php/default/login".parse().unwrap());
+        assert_eq!(check_response(&resp).as_deref(), Some("redirected to `/pvz/index.php/default/login`"));
+
+        let resp = AmfResponse::ok(&include_bytes!("../test_resp.amf")[..]);
+        assert_eq!(check_response(&resp), None);
+    }
+
+    #[test]
+    fn test_check_packet() {
+        let fault = |s: &str| object(vec![("faultString", string(s))].into_iter());
+        let packet = Packet::builder()
+            .version(Version::Amf0)
+            .body("/1/onStatus", "null", fault("今日次数已用完"))
+            .build()
+            .unwrap();
+        assert_eq!(check_packet(&packet), None);
+
+        // faults which mention login but are not about the session
+        for s in ["登录奖励已领取", "连续登录7天可领取", "login reward claimed", "session limit reached"] {
+            let packet = Packet::builder()
+                .version(Version::Amf0)
+                .body("/1/onStatus", "null", fault(s))
+                .build()
+                .unwrap();
+            assert_eq!(check_packet(&packet), None, "{}", s);
+        }
+
+        let packet = Packet::builder()
+            .version(Version::Amf0)
+            .body("/1/onResult", "null", string("请重新登录"))
+            .body("/2/onStatus", "null", fault("请重新登录"))
+            .build()
+            .unwrap();
+        assert!(check_packet(&packet).unwrap().contains("请重新登录"));
+    }
+}
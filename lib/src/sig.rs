@@ -0,0 +1,35 @@
+//! The `sig` of the php pages, e.g. `/pvz/index.php/Warehouse/index/sig/{sig}?{timestamp}`.
+//!
+//! The sig is taken as the hex md5 of the key of the client followed by the timestamp in
+//! milliseconds, which is also the query of the url. The key is not sent with the requests:
+//! it is a constant of the game client (`main.swf`), and is found by decompiling it, e.g. with
+//! JPEXS, where the pages are requested. It is set by
+//! [`ClientBuilder::sig_key`](crate::ClientBuilder::sig_key) or `sig_key` in the account config.
+//!
+//! The scheme is not yet checked against the game: the only captured page url (in `test_sign_captured`)
+//! has no known key. Until it is checked, the pages are only fetched when a key is configured, and a
+//! wrong sig fails those calls only. Check a key by
+//! `PVZOL_SIG_KEY=... cargo test -- --ignored test_sign_captured`.
+
+pub(crate) fn sign(key: &str, timestamp: u128) -> String {
+    format!("{:x}", md5::compute(format!("{}{}", key, timestamp)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        assert_eq!(sign("", 0), "cfcd208495d565ef66e7dff9f98764da");
+        assert_eq!(sign("key", 1660726559561), "87e9ad264803dcfa3c6b8deb45d3dc6c");
+    }
+
+    #[test]
+    #[ignore = "needs the sig key of the game client in `PVZOL_SIG_KEY`"]
+    fn test_sign_captured() {
+        // captured from the browser: /pvz/index.php/Warehouse/index/sig/11c58a61121e4a8b1f77abf6f0f5a1fa?1660726559561
+        let key = std::env::var("PVZOL_SIG_KEY").expect("`PVZOL_SIG_KEY` is not set");
+        assert_eq!(sign(&key, 1660726559561), "11c58a61121e4a8b1f77abf6f0f5a1fa");
+    }
+}
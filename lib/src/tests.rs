@@ -1,20 +1,670 @@
 
 use std::{path::{PathBuf}, env::current_dir};
 
-use crate::{game::sys::Quality};
+use crate::{cancel::CancellationToken, event::{Event, Progress}, game::sys::{Quality, QualityUpType}, Client, ErrorKind, Rejection};
+use mock::MockGateway;
 use util::*;
 
+mod mock;
 mod util;
 
 fn get_current_dir() -> PathBuf {
-    if cfg!(debug_assertions) {
-        current_dir().unwrap() //.join("tools")
-    } else {
-        current_dir().unwrap()
+    current_dir().unwrap() //.join("tools")
+}
+
+#[tokio::test]
+async fn test_mock_quality_up() -> Result<(), Box<dyn std::error::Error>> {
+    let gateway = MockGateway::start().await;
+    let client = gateway.client();
+
+    assert_eq!(client.quality_up(QualityUpType::General, 1.).await?, Quality::优秀);
+    assert_eq!(client.quality_up(QualityUpType::General, 1.).await?, Quality::精良);
+    assert_eq!(client.quality_up(QualityUpType::Moshen, 1.).await?, Quality::魔神);
+
+    let calls = gateway.calls();
+    assert_eq!(calls.len(), 3);
+    assert_eq!(calls[0].0, "api.apiorganism.qualityUp");
+    assert_eq!(calls[2].0, "api.apiorganism.quality12Up");
+    let (plant_id,): (f64,) = crate::amf::from_value(&calls[0].1)?;
+    assert_eq!(plant_id, 1.);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mock_skill_up_to() -> Result<(), Box<dyn std::error::Error>> {
+    let gateway = MockGateway::start().await;
+    let client = gateway.client();
+
+    let mut events = client.subscribe();
+    client.skill_up_to(1., 586., |_, uped| uped == 1, &CancellationToken::new()).await?;
+
+    let calls = gateway.calls();
+    assert_eq!(calls.len(), 2);
+    assert!(calls.iter().all(|(target, _)| target == "api.apiorganism.skillUp"));
+
+    assert_eq!(events.try_recv()?, Event::Progress(Progress::SkillUp { plant_id: 1., attempt: 1, old: 586., new: 586. }));
+    assert_eq!(events.try_recv()?, Event::Progress(Progress::SkillUp { plant_id: 1., attempt: 2, old: 586., new: 587. }));
+    assert!(events.try_recv().is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mock_rewards() -> Result<(), Box<dyn std::error::Error>> {
+    let gateway = MockGateway::start().await;
+    let client = gateway.client();
+
+    client.open_box(1., 10).await?;
+    assert_eq!(client.get_fuben_reward(4.).await?, (10, 5));
+    assert_eq!(client.get_fuben_award("medal", 4.).await?, 0.);
+    assert!(client.challenge(crate::game::sys::ChallengeType::Fuben, 58., [1.].into_iter()).await?);
+
+    // all duties are sent in a single request, a failed one does not stop the others.
+    assert_eq!(client.get_duty_rewards([1., 0., 2.].into_iter(), 3.).await?, 2);
+
+    let targets: Vec<String> = gateway.calls().into_iter().map(|(t, _)| t).collect();
+    assert_eq!(targets, [
+        "api.reward.openbox",
+        "api.fuben.reward",
+        "api.fuben.award",
+        "api.fuben.challenge",
+        "api.reward.lottery",
+        "api.duty.reward",
+        "api.duty.reward",
+        "api.duty.reward",
+    ]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mock_fault() {
+    let gateway = MockGateway::empty().await;
+    gateway.on("api.duty.reward", |_| mock::fault("1", "任务不存在"));
+    let client = gateway.client();
+
+    match client.get_duty_reward(0., 3.).await {
+        Err(ErrorKind::Fault(fault)) => assert_eq!(fault.fault_string, "任务不存在"),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    match client.skill_up(1., 1.).await {
+        Err(ErrorKind::Fault(fault)) => assert_eq!(fault.fault_code, "AMFPHP_INEXISTANT_METHOD"),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_mock_duty_rewards() -> Result<(), Box<dyn std::error::Error>> {
+    use std::{sync::atomic::{AtomicUsize, Ordering}, time::Duration};
+    use crate::{amf::amf0::{number, object}, retry::RetryPolicy};
+
+    // the too frequent duty is sent again after the backoff, the others are not.
+    let gateway = MockGateway::empty().await;
+    let n = AtomicUsize::new(0);
+    gateway.on("api.duty.reward", move |args| {
+        let (duty_id, _): (f64, f64) = crate::amf::from_value(args).unwrap();
+        match (duty_id, n.fetch_add(1, Ordering::SeqCst)) {
+            (0., _) => mock::fault("1", "任务不存在"),
+            (2., 1) => mock::fault("1", "操作过于频繁"),
+            _ => mock::result(object(vec![("user_exp", number(10.))].into_iter())),
+        }
+    });
+    let client = gateway.builder()
+        .retry(RetryPolicy::default().backoff(Duration::ZERO, Duration::ZERO))
+        .build()?;
+    let mut events = client.subscribe();
+    assert_eq!(client.get_duty_rewards([0., 2.].into_iter(), 3.).await?, 1);
+    let calls: Vec<(f64, f64)> = gateway.calls()
+        .iter()
+        .map(|(_, args)| crate::amf::from_value(args).unwrap())
+        .collect();
+    assert_eq!(calls, [(0., 3.), (2., 3.), (2., 3.)]);
+    let events: Vec<Event> = std::iter::from_fn(|| events.try_recv().ok()).collect();
+    assert!(matches!(events[1], Event::Retry { attempt: 1, .. }));
+    assert_eq!(events[2], Event::Progress(Progress::DutyReward { duty_id: 2., category_id: 3., error: None }));
+
+    // an error when no reward is claimed
+    match client.get_duty_rewards([0., 0.].into_iter(), 3.).await {
+        Err(ErrorKind::Fault(fault)) => assert_eq!(fault.fault_string, "任务不存在"),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    // nothing is sent once the client is cancelled
+    let token = CancellationToken::new();
+    let client = gateway.builder().cancellation(token.clone()).build()?;
+    token.cancel();
+    assert_eq!(client.get_duty_rewards([1.].into_iter(), 3.).await?, 0);
+    assert_eq!(gateway.calls().len(), 5);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mock_rejected() {
+    use crate::amf::amf0::{object, string};
+
+    let gateway = MockGateway::empty().await;
+    gateway.on("api.apiorganism.qualityUp", |_| mock::result(object(vec![
+        ("description", string("品质刷新书不足")),
+        ("code", string("12")),
+    ].into_iter())));
+    gateway.on("api.apiorganism.skillUp", |_| mock::result(string("ok")));
+    let client = gateway.client();
+
+    match client.quality_up(QualityUpType::General, 1.).await {
+        Err(ErrorKind::GameRejected { reason, description, code }) => {
+            assert_eq!(reason, Rejection::NotEnoughItems);
+            assert_eq!(description, "品质刷新书不足");
+            assert_eq!(code.as_deref(), Some("12"));
+        },
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    let e = client.skill_up(1., 1.).await.unwrap_err();
+    assert!(matches!(e, ErrorKind::Protocol { source: Some(_), .. }));
+    assert!(std::error::Error::source(&e).is_some());
+}
+
+#[tokio::test]
+async fn test_mock_loop_stops_when_exhausted() -> Result<(), Box<dyn std::error::Error>> {
+    use std::{sync::atomic::{AtomicUsize, Ordering}, time::Duration};
+    use crate::{amf::amf0::{object, string}, retry::RetryPolicy};
+
+    let gateway = MockGateway::empty().await;
+    let n = AtomicUsize::new(0);
+    gateway.on("api.apiorganism.qualityUp", move |_| {
+        let description = match n.fetch_add(1, Ordering::SeqCst) {
+            0 => "操作过于频繁",
+            1 => return mock::result(object(vec![("quality_name", string("优秀"))].into_iter())),
+            _ => "品质刷新书不足",
+        };
+        mock::result(object(vec![("description", string(description))].into_iter()))
+    });
+    let client = gateway.builder()
+        .retry(RetryPolicy::default().backoff(Duration::ZERO, Duration::ZERO))
+        .build()?;
+
+    let mut events = client.subscribe();
+    client.quality_up_to(QualityUpType::General, 1., |_, _| false, &CancellationToken::new()).await?;
+    assert_eq!(gateway.calls().len(), 3);
+
+    assert!(matches!(events.try_recv()?, Event::Retry { attempt: 1, .. }));
+    assert_eq!(events.try_recv()?, Event::Progress(Progress::QualityUp {
+        plant_id: 1.,
+        attempt: 1,
+        old: None,
+        new: Quality::优秀,
+    }));
+    match events.try_recv()? {
+        Event::Progress(Progress::Stopped { reason, message }) => {
+            assert_eq!(reason, Rejection::NotEnoughItems);
+            assert_eq!(message, "品质刷新书不足");
+        },
+        other => panic!("unexpected event: {:?}", other),
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mock_loop_cancelled() -> Result<(), Box<dyn std::error::Error>> {
+    let gateway = MockGateway::start().await;
+    let token = CancellationToken::new();
+    let client = gateway.builder()
+        .on_event({
+            let token = token.clone();
+            move |event| {
+                // cancelled while the second request is done, so there is no third one
+                if let Event::Progress(Progress::QualityUp { attempt: 2, .. }) = event {
+                    token.cancel();
+                }
+            }
+        })
+        .build()?;
+
+    let mut events = client.subscribe();
+    client.quality_up_to(QualityUpType::General, 1., |_, _| false, &token).await?;
+    assert_eq!(gateway.calls().len(), 2);
+    assert!(!client.cancellation_token().is_cancelled());
+
+    let events: Vec<Event> = std::iter::from_fn(|| events.try_recv().ok()).collect();
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[2], Event::Progress(Progress::Cancelled));
+
+    // the next loop on the same client still runs
+    client.quality_up_to(QualityUpType::General, 1., |i, _| i == 3, &CancellationToken::new()).await?;
+    assert_eq!(gateway.calls().len(), 5);
+
+    // the token of the client stops every loop
+    let token = CancellationToken::new();
+    let client = gateway.builder().cancellation(token.clone()).build()?;
+    token.cancel();
+    assert_eq!(client.open_box_repeat(1., 1, 3, &CancellationToken::new()).await?, 0);
+    assert_eq!(gateway.calls().len(), 5);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mock_sys_info() -> Result<(), Box<dyn std::error::Error>> {
+    use std::sync::Arc;
+    use crate::game::{sys::{sys_info, GetSysInfo}, user::UserTool};
+
+    let gateway = MockGateway::empty().await;
+    gateway.file("/pvz/php_xml/tool.xml", &include_bytes!("../test_tool.xml")[..]);
+    gateway.file("/pvz/php_xml/organism.xml", &include_bytes!("../test_organism.xml")[..]);
+    let client = gateway.client();
+
+    assert_eq!(client.get_tools().await?.len(), 3);
+    let fetched = gateway.fetched();
+    assert!(fetched[0].starts_with("/pvz/php_xml/tool.xml?"), "with a timestamp: {}", fetched[0]);
+
+    // the only test which initializes the shared data
+    assert!(matches!(sys_info(), Err(ErrorKind::NotInitialized)));
+    let loaded = client.init_sys_info().await?;
+    assert_eq!(loaded.organisms()[1].name, "双发射手");
+    assert_eq!(loaded.organism(2).map(|o| o.name.as_str()), Some("双发射手"));
+    assert_eq!(loaded.organism_by_name("双发射手").map(|o| o.id), Some(2));
+    assert_eq!(loaded.tool_by_name("进化书").map(|t| t.tool_id), Some(5));
+    assert_eq!(sys_info()?.tools()[2].tool_id, 30);
+    assert!(Arc::ptr_eq(&client.init_sys_info().await?, &loaded));
+    assert_eq!(gateway.fetched().len(), 3, "initialized once");
+
+    // ids beyond the number of items are not found instead of panicking
+    let tool = |id| UserTool { id, amount: 1 };
+    assert_eq!(tool(30).get_tool(&loaded)?.tool_id, 30);
+    assert!(matches!(tool(1000).get_tool(&loaded), Err(ErrorKind::NotFound { kind: "tool", id: 1000 })));
+    assert!(loaded.organism(1000).is_none());
+
+    gateway.file("/pvz/php_xml/tool.xml", r#"<tools><item id="7" name="新道具"/></tools>"#);
+    let reloaded = client.reload_sys_info().await?;
+    assert_eq!(sys_info()?.tools().len(), 1);
+    assert!(Arc::ptr_eq(&sys_info()?, &reloaded));
+    // the old handle is untouched
+    assert_eq!(loaded.tools().len(), 3);
+
+    gateway.file("/pvz/php_xml/tool.xml", "");
+    assert!(matches!(client.get_tools().await, Err(ErrorKind::EmptyResponse)));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mock_sys_cache() -> Result<(), Box<dyn std::error::Error>> {
+    use std::time::Duration;
+    use crate::game::sys::{GetSysInfo, SysCache};
+
+    let dir = std::env::temp_dir().join(format!("pvzol-sys-cache-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let gateway = MockGateway::empty().await;
+    gateway.file("/pvz/php_xml/tool.xml", &include_bytes!("../test_tool.xml")[..]);
+    let cache = SysCache::new(&dir);
+
+    // downloaded once, then read from the cache
+    let client = gateway.builder().sys_cache(cache.clone()).build()?;
+    let tools = client.get_tools().await?;
+    assert_eq!(tools.len(), 3);
+    assert_eq!(client.get_tools().await?, tools);
+    assert_eq!(gateway.fetched().len(), 1);
+    let host = gateway.url().host_str().unwrap().to_owned();
+    let port = gateway.url().port().unwrap();
+    assert!(dir.join(format!("{}-{}", host, port)).join("tool.toml").exists());
+
+    // stale, validated by the etag
+    let client = gateway.builder().sys_cache(cache.clone().max_age(Duration::ZERO)).build()?;
+    assert_eq!(client.get_tools().await?, tools);
+    assert_eq!(gateway.fetched().len(), 2);
+
+    // changed on the server
+    gateway.file("/pvz/php_xml/tool.xml", r#"<tools><item id="1" name="新道具"/></tools>"#);
+    let tools = client.get_tools().await?;
+    assert_eq!(tools.len(), 1);
+    assert_eq!(tools[0].name, "新道具");
+
+    // offline, without any request
+    let client = gateway.builder().sys_cache(cache.clone().offline(true)).build()?;
+    assert_eq!(client.get_tools().await?, tools);
+    assert!(matches!(client.get_organisms().await, Err(ErrorKind::Config(_))));
+    assert_eq!(gateway.fetched().len(), 3);
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mock_warehouse() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::game::user::GetUserInfo;
+
+    let gateway = MockGateway::empty().await;
+    gateway.file("/pvz/index.php/Warehouse/index/sig/*", &include_bytes!("../test_warehouse.xml")[..]);
+
+    let client = gateway.client();
+    assert!(matches!(client.get_warehouse().await, Err(ErrorKind::Config(_))), "no sig key");
+
+    let client = gateway.builder().sig_key("key").build()?;
+    let (tools, organisms) = client.get_warehouse().await?;
+    assert_eq!(tools.len(), 3);
+    assert_eq!(organisms[0].skills.len(), 2);
+
+    let fetched = gateway.fetched();
+    let (page, timestamp) = fetched[0].split_once('?').unwrap();
+    let sig = page.strip_prefix("/pvz/index.php/Warehouse/index/sig/").unwrap();
+    assert_eq!(sig, crate::sig::sign("key", timestamp.parse()?));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mock_refresh_user() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::game::{sys::ChallengeType, user::GetUserInfo};
+
+    let gateway = MockGateway::empty().await;
+    gateway.file("/pvz/index.php/default/user/sig/*", &include_bytes!("../test_user.xml")[..]);
+    let client = gateway.builder().sig_key("key").build()?;
+    assert!(client.user().is_none());
+
+    let user = client.refresh_user().await?;
+    assert_eq!(user.money, 2500000);
+    assert_eq!(user.remaining_challenges(&ChallengeType::Stone), 10);
+    assert_eq!(client.user(), Some(user));
+
+    gateway.file("/pvz/index.php/default/user/sig/*", r#"<user id="8812345" money="0"/>"#);
+    assert_eq!(client.get_user().await?.money, 0);
+    assert_eq!(client.user().map(|u| u.money), Some(2500000), "kept until refreshed");
+    assert_eq!(client.refresh_user().await?.money, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cassette_record_replay() -> Result<(), Box<dyn std::error::Error>> {
+    use std::sync::Arc;
+    use crate::transport::{cassette::*, ReqwestTransport};
+
+    let toml = {
+        let gateway = MockGateway::start().await;
+        let recorder = Arc::new(RecordingTransport::new(ReqwestTransport::new()));
+        let client = gateway.builder()
+            .transport(recorder.clone())
+            .build()?;
+
+        assert_eq!(client.quality_up(QualityUpType::General, 1.).await?, Quality::优秀);
+        assert_eq!(client.get_fuben_reward(4.).await?, (10, 5));
+
+        recorder.cassette().to_toml()?
+    };
+    assert!(toml.contains("cookie = \"<redacted>\""));
+    assert!(!toml.contains("mock"));
+    assert!(toml.contains("calls = [\"api.apiorganism.qualityUp\"]"));
+
+    // the gateway is gone, and the calls are answered by the cassette.
+    let replay = Arc::new(ReplayTransport::new(Cassette::from_bytes(&toml)?));
+    let client = Client::builder()
+        .server(1)
+        .pacing(crate::pacing::Pacing::unlimited())
+        .transport(replay.clone())
+        .build()?;
+    assert_eq!(client.quality_up(QualityUpType::General, 1.).await?, Quality::优秀);
+    assert!(client.get_fuben_reward(5.).await.is_err(), "different arguments");
+    assert_eq!(replay.remaining(), 0);
+    assert!(client.get_fuben_reward(4.).await.is_err(), "no more interactions");
+
+    Ok(())
+}
+
+#[test]
+fn test_cassette_redact_session() -> Result<(), Box<dyn std::error::Error>> {
+    use bytes::Bytes;
+    use crate::{amf::packet::{Packet, ReadAs}, transport::{cassette::Interaction, AmfRequest, AmfResponse}};
+
+    let request = AmfRequest {
+        url: "http://pvz-s1.youkia.com/pvz/amf/".parse()?,
+        headers: Default::default(),
+        body: Bytes::from_static(include_bytes!("../test_req.amf")),
+    };
+    let mut response = AmfResponse::ok(&include_bytes!("../test_resp.amf")[..]);
+    response.headers.insert("set-cookie", "PHPSESSID=erg264ru8e8og5o9ahocqclv11; path=/".parse()?);
+
+    let interaction = Interaction::record(&request, &response);
+    assert_eq!(interaction.calls, ["api.apiorganism.qualityUp"]);
+    assert_eq!(interaction.response_headers["set-cookie"], "PHPSESSID=<redacted>; path=/");
+
+    let mut replayed = interaction.to_response()?;
+    let packet: Packet = replayed.body.read_as()?;
+    let original: Packet = Bytes::from_static(include_bytes!("../test_resp.amf")).read_as()?;
+    assert_eq!(packet.headers[0].data, crate::amf::amf0::string("?PHPSESSID=<redacted>").into());
+    assert_eq!(packet.bodies, original.bodies);
+
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_retry() -> Result<(), Box<dyn std::error::Error>> {
+    use std::sync::{Arc, Mutex};
+    use reqwest::StatusCode;
+    use crate::{
+        amf::{amf0, packet::{IntoBytes, Packet}, Version},
+        event::Event,
+        pacing::Pacing,
+        retry::RetryPolicy,
+        transport::{AmfRequest, AmfResponse, AmfTransport, BoxFuture},
+    };
+
+    /// answer with the scripted responses in order.
+    struct Flaky(Mutex<Vec<crate::Result<AmfResponse>>>);
+
+    impl AmfTransport for Flaky {
+        fn send(&self, _: AmfRequest) -> BoxFuture<'_, crate::Result<AmfResponse>> {
+            let next = self.0.lock().unwrap().remove(0);
+            Box::pin(async move { next })
+        }
     }
+
+    let ok = Packet::builder()
+        .version(Version::Amf0)
+        .body("/1/onResult", "null", amf0::object(vec![("now_id", amf0::number(2.))].into_iter()))
+        .build()?
+        .into_bytes();
+    let status = |status: StatusCode| Ok(AmfResponse { status, ..AmfResponse::ok(vec![]) });
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let flaky = Flaky(Mutex::new(vec![
+        // only the unsent requests are retried by default
+        Err(ErrorKind::Connect("connection refused".into())),
+        Ok(AmfResponse::ok(ok.clone())),
+        status(StatusCode::BAD_GATEWAY),
+        // all the transient failures are retried for the idempotent calls
+        Err(ErrorKind::Network("connection reset".into())),
+        status(StatusCode::BAD_GATEWAY),
+        Ok(AmfResponse::ok(vec![])),
+        Ok(AmfResponse::ok(ok.clone())),
+        status(StatusCode::NOT_FOUND),
+        Err(ErrorKind::Connect("connection refused".into())),
+    ]));
+    let cancel = CancellationToken::new();
+    let client = Client::builder()
+        .server(1)
+        .pacing(Pacing::unlimited())
+        .retry(RetryPolicy::default().attempts(4))
+        .on_event({
+            let events = events.clone();
+            move |e| events.lock().unwrap().push(e.clone())
+        })
+        .cancellation(cancel.clone())
+        .transport(flaky)
+        .build()?;
+    let retries = || events.lock().unwrap()
+        .iter()
+        .filter_map(|e| match e {
+            Event::Retry { attempt, .. } => Some(*attempt),
+            _ => None,
+        })
+        .collect::<Vec<u32>>();
+
+    assert_eq!(client.skill_up(1., 1.).await?, 2.);
+    assert_eq!(retries(), [1]);
+    // it may have reached the server
+    assert!(matches!(client.skill_up(1., 1.).await, Err(ErrorKind::HttpStatus(StatusCode::BAD_GATEWAY))));
+    assert_eq!(retries(), [1]);
+
+    #[derive(serde::Deserialize)]
+    struct SkillUp {
+        now_id: f64,
+    }
+    let start = tokio::time::Instant::now();
+    let res: SkillUp = client.call_idempotent("api.apiorganism.skillUp", (1., 1.)).await?;
+    assert_eq!(res.now_id, 2.);
+    assert_eq!(start.elapsed(), std::time::Duration::from_secs(1 + 2 + 4));
+    assert_eq!(retries(), [1, 1, 2, 3]);
+
+    // permanent errors are not retried
+    let res: crate::Result<SkillUp> = client.call_idempotent("api.apiorganism.skillUp", (1., 1.)).await;
+    assert!(matches!(res, Err(ErrorKind::HttpStatus(StatusCode::NOT_FOUND))));
+    assert_eq!(retries(), [1, 1, 2, 3]);
+
+    // cancelled while waiting to retry, it is not sent again
+    let call = client.skill_up(1., 1.);
+    let cancel_soon = async {
+        tokio::task::yield_now().await;
+        cancel.cancel();
+    };
+    let (res, _) = tokio::join!(call, cancel_soon);
+    assert!(matches!(res, Err(ErrorKind::Cancelled)));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mock_set_cookie() -> Result<(), Box<dyn std::error::Error>> {
+    let gateway = MockGateway::start().await;
+    let client = gateway.builder()
+        .cookie("pvz_youkia", "user")
+        .build()?;
+
+    client.open_box(1., 1).await?;
+    gateway.set_cookie("PHPSESSID=refreshed; path=/");
+    client.open_box(1., 1).await?;
+    client.open_box(1., 1).await?;
+
+    assert_eq!(gateway.cookies(), [
+        "PHPSESSID=mock; pvz_youkia=user",
+        "PHPSESSID=mock; pvz_youkia=user",
+        "PHPSESSID=refreshed; pvz_youkia=user",
+    ]);
+
+    assert_eq!(client.remove_cookie("pvz_youkia").as_deref(), Some("user"));
+    assert_eq!(client.set_cookie("lang", "zh"), None);
+    let account = client.account_info();
+    assert_eq!(account.cookies.len(), 2);
+    assert_eq!(account.cookies["PHPSESSID"], "refreshed");
+
+    // only the cookie values are patched, the rest of the file is kept as it is
+    let config = "# 主号\nserver = 6\nname = \"nmh\"\n\n[cookies]\nPHPSESSID = \"mock\" # 一周后过期\npvz_youkia = \"user\"\n\n[http]\ntimeout = 10\n";
+    assert_eq!(account.patch_cookies(config)?, "# 主号\nserver = 6\nname = \"nmh\"\n\n[cookies]\nPHPSESSID = \"refreshed\" # 一周后过期\npvz_youkia = \"user\"\nlang = \"zh\"\n\n[http]\ntimeout = 10\n");
+    assert_eq!(account.patch_cookies("server = 6\n")?, "server = 6\n\n[cookies]\nPHPSESSID = \"refreshed\"\nlang = \"zh\"\n");
+    // an inline table is not patched
+    assert!(matches!(account.patch_cookies("server = 6\ncookies = { PHPSESSID = \"mock\" }\n"), Err(ErrorKind::Config(_))));
+
+    // the account of the config is carried through
+    let client = Client::builder()
+        .account(crate::AccountInfo {
+            server: 6,
+            base_url: Some(gateway.url()),
+            cookies: [("PHPSESSID".to_owned(), "mock".to_owned())].into_iter().collect(),
+            ..Default::default()
+        })
+        .pacing(crate::pacing::Pacing::unlimited())
+        .build()?;
+    // the base url of the account is not replaced by the one of server 6
+    assert_eq!(client.server_url(), &gateway.url());
+    client.set_cookie("PHPSESSID", "refreshed");
+    let account = client.account_info();
+    assert_eq!(account.server, 6);
+    assert_eq!(account.base_url, Some(gateway.url()));
+    let saved = crate::AccountInfo::from_bytes(account.patch_cookies("server = 6\n[cookies]\nPHPSESSID = \"mock\"\n")?)?;
+    assert_eq!(saved.server, 6);
+    assert_eq!(saved.cookies["PHPSESSID"], "refreshed");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mock_server_registry() -> Result<(), Box<dyn std::error::Error>> {
+    let gateway = MockGateway::start().await;
+    let account = crate::AccountInfo::from_bytes(format!(r#"
+        server = 90
+
+        [cookies]
+        PHPSESSID = "mock"
+
+        [[servers]]
+        id = 90
+        name = "合区"
+        base_url = "{}"
+    "#, gateway.url()))?;
+
+    let client = Client::builder()
+        .account(account)
+        .pacing(crate::pacing::Pacing::unlimited())
+        .build()?;
+    assert_eq!(client.server().name, "合区");
+    client.open_box(1., 1).await?;
+    assert_eq!(gateway.calls().len(), 1);
+
+    // an explicit base url wins over the registry
+    let client = Client::builder()
+        .server(3)
+        .server_url("https://mirror.example.com".parse()?)
+        .build()?;
+    assert_eq!(client.server().id, 3);
+    assert_eq!(client.server_url().as_str(), "https://mirror.example.com/");
+
+    // a server id out of range is an invalid config, not server 0
+    let e = crate::AccountInfo::from_bytes("server = 256
+[cookies]
+").unwrap_err();
+    assert!(matches!(e, ErrorKind::Config(_)));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mock_session_expired() -> Result<(), Box<dyn std::error::Error>> {
+    use std::{collections::HashMap, sync::{atomic::{AtomicUsize, Ordering}, Arc}};
+
+    let gateway = MockGateway::start().await;
+    gateway.require_cookie("PHPSESSID=fresh");
+
+    let client = gateway.client();
+    match client.open_box(1., 1).await {
+        Err(ErrorKind::SessionExpired(reason)) => assert!(reason.contains("login")),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    let reauths = Arc::new(AtomicUsize::new(0));
+    let client = gateway.builder()
+        .reauth({
+            let reauths = reauths.clone();
+            move || {
+                reauths.fetch_add(1, Ordering::SeqCst);
+                async { Ok(HashMap::from([("PHPSESSID".to_owned(), "fresh".to_owned())])) }
+            }
+        })
+        .build()?;
+    client.open_box(1., 1).await?;
+    client.open_box(1., 1).await?;
+    assert_eq!(reauths.load(Ordering::SeqCst), 1);
+    assert_eq!(client.get_cookie("PHPSESSID").as_deref(), Some("fresh"));
+
+    // the session is still expired after re-auth, only retried once.
+    gateway.require_cookie("PHPSESSID=fresher");
+    assert!(matches!(client.open_box(1., 1).await, Err(ErrorKind::SessionExpired(_))));
+    assert_eq!(reauths.load(Ordering::SeqCst), 2);
+
+    Ok(())
 }
 
 #[tokio::test]
+#[ignore = "uses a real account on the live server"]
 async fn test_quality_up() -> Result<(), Box<dyn std::error::Error>> {
 
     let client = load_nmh().await?;
@@ -24,13 +674,14 @@ async fn test_quality_up() -> Result<(), Box<dyn std::error::Error>> {
     let plant_ids = [ 1996336.,  ];
 
     for p in plant_ids {
-        client.quality_up_to(p, |_, q| q == to_quality).await?;
+        client.quality_up_to(QualityUpType::General, p, |_, q| q == to_quality, &CancellationToken::new()).await?;
     }
 
     Ok(())
 }
 
 #[tokio::test]
+#[ignore = "uses a real account on the live server"]
 async fn test_skill_up() -> Result<(), Box<dyn std::error::Error>> {
 
     let client = load_nmh().await?;
@@ -41,15 +692,16 @@ async fn test_skill_up() -> Result<(), Box<dyn std::error::Error>> {
 
     for (p, (sk, up)) in plant_ids.into_iter()
         .zip(
-            skill_ids.into_iter().zip(ups.into_iter())
+            skill_ids.into_iter().zip(ups)
         ) {
-        client.skill_up_to(p, sk, |_, uped| uped == up).await?;
+        client.skill_up_to(p, sk, |_, uped| uped == up, &CancellationToken::new()).await?;
     }
 
     Ok(())
 }
 
 #[tokio::test]
+#[ignore = "uses a real account on the live server"]
 async fn test_duty_rewards() -> Result<(), Box<dyn std::error::Error>> {
 
     let client = load_nmh().await?;
@@ -68,6 +720,7 @@ async fn test_duty_rewards() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 #[tokio::test]
+#[ignore = "uses a real account on the live server"]
 async fn test_fuben_rewards() -> Result<(), Box<dyn std::error::Error>> {
 
     let client = load_nmh().await?;
@@ -77,12 +730,13 @@ async fn test_fuben_rewards() -> Result<(), Box<dyn std::error::Error>> {
     // // client.reset_fuben_reward(4.).await?;
     // // client.reset_fuben_reward(5.).await?;
     // // client.reset_and_get_fuben_reward(2., 300).await?;
-    client.reset_and_get_fuben_reward(4., 1000).await?;
+    client.reset_and_get_fuben_reward(4., 1000, &CancellationToken::new()).await?;
 
     Ok(())
 }
 
 #[tokio::test]
+#[ignore = "uses a real account on the live server"]
 async fn test_fuben_challenge() -> Result<(), Box<dyn std::error::Error>> {
 
     let client = load_nmh().await?;
@@ -90,7 +744,7 @@ async fn test_fuben_challenge() -> Result<(), Box<dyn std::error::Error>> {
     let fuben_id = 58.;
     let plant_ids = vec![1901265.];
 
-    client.challenge_fuben_repeat(fuben_id, plant_ids, 16).await?;
+    client.challenge_fuben_repeat(fuben_id, plant_ids, 16, &CancellationToken::new()).await?;
 
     Ok(())
 }
@@ -0,0 +1,329 @@
+//! A local amf gateway which answers the calls used by `Client` with scripted results,
+//! so the whole `Client` can be tested without a real account.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex},
+};
+
+use bytes::Bytes;
+use reqwest::Url;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+use crate::{
+    amf::{
+        self,
+        amf0::{array, number, object, string},
+        packet::{IntoBytes, Packet, ReadAs, Response},
+        Amf0Value, Value, Version,
+    },
+    pacing::Pacing,
+    Client, ClientBuilder,
+};
+
+/// answer the arguments of a call.
+pub type Handler = Box<dyn Fn(&Value) -> Response + Send + Sync>;
+
+#[derive(Default)]
+struct State {
+    handlers: Mutex<HashMap<String, Handler>>,
+    calls: Mutex<Vec<(String, Value)>>,
+    /// the `Cookie` header of every request.
+    cookies: Mutex<Vec<String>>,
+    set_cookies: Mutex<Vec<String>>,
+    /// redirect to the login page if the `Cookie` header is not this.
+    required_cookie: Mutex<Option<String>>,
+    /// files served for `GET`, by path without the query.
+    files: Mutex<HashMap<String, Bytes>>,
+    /// the path and query of every `GET`.
+    fetched: Mutex<Vec<String>>,
+}
+
+pub struct MockGateway {
+    addr: SocketAddr,
+    state: Arc<State>,
+    task: JoinHandle<()>,
+}
+
+impl MockGateway {
+    /// start a gateway which knows nothing, every call is answered with a fault.
+    pub async fn empty() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(State::default());
+
+        let task = tokio::spawn({
+            let state = state.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(serve(stream, state.clone()));
+                }
+            }
+        });
+
+        MockGateway { addr, state, task }
+    }
+
+    /// start a gateway with the default script, see `script_default`.
+    pub async fn start() -> Self {
+        let gateway = Self::empty().await;
+        gateway.script_default();
+        gateway
+    }
+
+    /// answer the calls of `target_uri` with `handler`, replacing the old one.
+    pub fn on<F>(&self, target_uri: &str, handler: F) -> &Self
+    where
+        F: Fn(&Value) -> Response + Send + Sync + 'static,
+    {
+        self.state.handlers
+            .lock()
+            .unwrap()
+            .insert(target_uri.to_owned(), Box::new(handler));
+        self
+    }
+
+    /// the root url of the gateway, like the url of a real server.
+    pub fn url(&self) -> Url {
+        Url::parse(&format!("http://{}/", self.addr)).unwrap()
+    }
+
+    pub fn builder(&self) -> ClientBuilder {
+        Client::builder()
+            .server_url(self.url())
+            .pacing(Pacing::unlimited())
+            .cookie("PHPSESSID", "mock")
+    }
+
+    pub fn client(&self) -> Client {
+        self.builder().build().unwrap()
+    }
+
+    /// send `Set-Cookie: {set_cookie}` with every response from now on.
+    pub fn set_cookie(&self, set_cookie: &str) -> &Self {
+        self.state.set_cookies.lock().unwrap().push(set_cookie.to_owned());
+        self
+    }
+
+    /// expire the session, redirect requests to the login page unless they have the `Cookie` header `cookie`.
+    pub fn require_cookie(&self, cookie: &str) -> &Self {
+        *self.state.required_cookie.lock().unwrap() = Some(cookie.to_owned());
+        self
+    }
+
+    /// serve `content` for `GET path`, with an `ETag` of its length.
+    ///
+    /// a `path` ending with `*` is a prefix, e.g. for the signed pages.
+    pub fn file(&self, path: &str, content: impl Into<Bytes>) -> &Self {
+        self.state.files.lock().unwrap().insert(path.to_owned(), content.into());
+        self
+    }
+
+    /// the path and query of every `GET` so far.
+    pub fn fetched(&self) -> Vec<String> {
+        self.state.fetched.lock().unwrap().clone()
+    }
+
+    /// the `Cookie` header of every request so far.
+    pub fn cookies(&self) -> Vec<String> {
+        self.state.cookies.lock().unwrap().clone()
+    }
+
+    /// all the calls received so far, as `(target_uri, args)`.
+    pub fn calls(&self) -> Vec<(String, Value)> {
+        self.state.calls.lock().unwrap().clone()
+    }
+
+    /// - `api.apiorganism.qualityUp`: one quality higher each call, starting from `优秀`
+    /// - `api.apiorganism.quality12Up`: `魔神`
+    /// - `api.apiorganism.skillUp`: the skill id goes up every second call
+    /// - `api.reward.openbox`: no tools
+    /// - `api.duty.reward`: a fault for duty `0`
+    /// - `api.fuben.reward`: 10 integral and 5 medals
+    /// - `api.fuben.award`: `next` is 0
+    /// - `api.fuben.challenge`: always win
+    /// - `api.reward.lottery`: nothing
+    pub fn script_default(&self) {
+        const QUALITIES: [&str; 6] = ["优秀", "精良", "极品", "史诗", "传说", "神器"];
+        let quality_ups = AtomicUsize::new(0);
+        self.on("api.apiorganism.qualityUp", move |_| {
+            let i = quality_ups.fetch_add(1, Ordering::SeqCst);
+            let quality = QUALITIES[i.min(QUALITIES.len() - 1)];
+            result(object(vec![("quality_name", string(quality))].into_iter()))
+        });
+        self.on("api.apiorganism.quality12Up", |_| {
+            result(object(vec![("quality_name", string("魔神"))].into_iter()))
+        });
+
+        let skill_ups = AtomicUsize::new(0);
+        self.on("api.apiorganism.skillUp", move |args| {
+            let (_, skill_id): (f64, f64) = amf::from_value(args).unwrap();
+            let i = skill_ups.fetch_add(1, Ordering::SeqCst);
+            let now_id = if i % 2 == 1 { skill_id + 1. } else { skill_id };
+            result(object(vec![("now_id", number(now_id))].into_iter()))
+        });
+
+        self.on("api.reward.openbox", |_| {
+            result(object(vec![("tools", array(vec![]))].into_iter()))
+        });
+        self.on("api.duty.reward", |args| {
+            let (duty_id, _): (f64, f64) = amf::from_value(args).unwrap();
+            if duty_id == 0. {
+                return fault("1", "任务不存在");
+            }
+            result(object(vec![("user_exp", number(10.))].into_iter()))
+        });
+        self.on("api.fuben.reward", |_| {
+            result(object(vec![
+                ("integral", number(10.)),
+                ("medal", object(vec![("amount", number(5.))].into_iter())),
+            ].into_iter()))
+        });
+        self.on("api.fuben.award", |_| {
+            result(object(vec![("next", number(0.))].into_iter()))
+        });
+        self.on("api.fuben.challenge", |_| {
+            result(object(vec![
+                ("is_winning", Amf0Value::Boolean(true)),
+                ("awards_key", string("mock-awards")),
+            ].into_iter()))
+        });
+        self.on("api.reward.lottery", |_| {
+            result(object(vec![("tools", array(vec![]))].into_iter()))
+        });
+    }
+}
+
+impl Drop for MockGateway {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+pub fn result(value: Amf0Value) -> Response {
+    Response::Result(value.into())
+}
+
+pub fn fault(code: &str, description: &str) -> Response {
+    Response::Status(object(vec![
+        ("faultCode", string(code)),
+        ("faultString", string(description)),
+    ].into_iter()).into())
+}
+
+/// serve a single http request, then close the connection.
+async fn serve(stream: TcpStream, state: Arc<State>) {
+    let mut stream = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    if stream.read_line(&mut request_line).await.is_err() {
+        return;
+    }
+    let mut content_length = 0;
+    let mut cookie = String::new();
+    let mut if_none_match = None;
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+            return;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            } else if name.eq_ignore_ascii_case("cookie") {
+                cookie = value.trim().to_owned();
+                state.cookies.lock().unwrap().push(cookie.clone());
+            } else if name.eq_ignore_ascii_case("if-none-match") {
+                if_none_match = Some(value.trim().to_owned());
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    if stream.read_exact(&mut body).await.is_err() {
+        return;
+    }
+
+    let required_cookie = state.required_cookie.lock().unwrap().clone();
+    let mut etag = None;
+    let (status, body) = if required_cookie.is_some_and(|c| c != cookie) {
+        ("302 Found\r\nLocation: /pvz/index.php/default/login", Bytes::new())
+    } else if request_line.starts_with("POST /pvz/amf/ ") {
+        match Bytes::from(body).read_as() {
+            Ok(packet) => ("200 OK", answer(packet, &state).into_bytes()),
+            Err(_) => ("400 Bad Request", Bytes::new()),
+        }
+    } else if let Some(target) = request_line.strip_prefix("GET ").and_then(|l| l.split(' ').next()) {
+        state.fetched.lock().unwrap().push(target.to_owned());
+        let path = target.split('?').next().unwrap_or_default();
+        let files = state.files.lock().unwrap();
+        let file = files.get(path).or_else(|| {
+            files
+                .iter()
+                .find(|(p, _)| p.strip_suffix('*').is_some_and(|prefix| path.starts_with(prefix)))
+                .map(|(_, content)| content)
+        });
+        match file {
+            Some(content) => {
+                // the files are validated by the length, it is enough for the tests.
+                let tag = format!("\"{}\"", content.len());
+                let not_modified = if_none_match.as_ref() == Some(&tag);
+                etag = Some(tag);
+                if not_modified {
+                    ("304 Not Modified", Bytes::new())
+                } else {
+                    ("200 OK", content.clone())
+                }
+            },
+            None => ("404 Not Found", Bytes::new()),
+        }
+    } else {
+        ("404 Not Found", Bytes::new())
+    };
+
+    let mut head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/x-amf\r\nContent-Length: {}\r\nConnection: close\r\n",
+        status,
+        body.len(),
+    );
+    if let Some(etag) = etag {
+        head.push_str(&format!("ETag: {}\r\n", etag));
+    }
+    for set_cookie in state.set_cookies.lock().unwrap().iter() {
+        head.push_str(&format!("Set-Cookie: {}\r\n", set_cookie));
+    }
+    head.push_str("\r\n");
+    let stream = stream.get_mut();
+    let _ = stream.write_all(head.as_bytes()).await;
+    let _ = stream.write_all(&body).await;
+    let _ = stream.shutdown().await;
+}
+
+fn answer(req: Packet, state: &State) -> Packet {
+    let handlers = state.handlers.lock().unwrap();
+    let mut builder = Packet::builder().version(Version::Amf0);
+    for body in req.bodies {
+        state.calls
+            .lock()
+            .unwrap()
+            .push((body.target_uri.clone(), body.data.clone()));
+
+        let response = match handlers.get(&body.target_uri) {
+            Some(handler) => handler(&body.data),
+            None => fault("AMFPHP_INEXISTANT_METHOD", &format!("`{}` is not scripted", body.target_uri)),
+        };
+        builder = match response {
+            Response::Result(data) => builder.body(format!("{}/onResult", body.response_uri), "null", data),
+            Response::Status(data) => builder.body(format!("{}/onStatus", body.response_uri), "null", data),
+        };
+    }
+    builder.build().unwrap()
+}
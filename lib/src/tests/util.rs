@@ -1,4 +1,7 @@
-use crate::{AccountInfo, Client, Result, game::user::UserInfo};
+use crate::{
+    game::user::{GetUserInfo, UserOrganism, UserTool},
+    AccountInfo, Client, Result,
+};
 
 #[allow(dead_code)]
 pub async fn load_errw() -> Result<Client> {
@@ -25,6 +28,7 @@ pub async fn load_nmh() -> Result<Client> {
     Ok(client)
 }
 
-pub async fn load_user_info() -> Result<UserInfo> {
-    todo!()
+#[allow(dead_code)]
+pub async fn load_user_info() -> Result<(Vec<UserTool>, Vec<UserOrganism>)> {
+    load_errw().await?.get_warehouse().await
 }
@@ -0,0 +1,343 @@
+//! How packets are sent to the amf gateway.
+//!
+//! [`Client`](crate::Client) only builds the request and parses the response,
+//! the http round trip is done by an [`AmfTransport`], which is
+//! [`ReqwestTransport`] by default.
+
+use std::{collections::HashMap, future::Future, pin::Pin, time::Duration};
+
+use bytes::Bytes;
+use reqwest::{header::HeaderMap, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+
+use crate::{ErrorKind, Result};
+
+pub mod cassette;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// An http `POST` to the amf gateway.
+#[derive(Debug, Clone)]
+pub struct AmfRequest {
+    pub url: Url,
+    pub headers: HeaderMap,
+    /// the encoded request packet.
+    pub body: Bytes,
+}
+
+#[derive(Debug, Clone)]
+pub struct AmfResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    /// the encoded response packet.
+    pub body: Bytes,
+}
+
+impl AmfResponse {
+    /// a `200 OK` response with no headers.
+    pub fn ok(body: impl Into<Bytes>) -> Self {
+        AmfResponse {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: body.into(),
+        }
+    }
+}
+
+/// Send request bytes and return response bytes.
+///
+/// The returned error is for failures of the round trip itself,
+/// a response with any status should be returned as `Ok`.
+pub trait AmfTransport: Send + Sync {
+    fn send(&self, request: AmfRequest) -> BoxFuture<'_, Result<AmfResponse>>;
+
+    /// an http `GET` of `url`, e.g. the game data files, the body of `request` is ignored.
+    ///
+    /// Not supported by default.
+    fn fetch(&self, request: AmfRequest) -> BoxFuture<'_, Result<AmfResponse>> {
+        Box::pin(async move {
+            Err(ErrorKind::Unsupported(format!("the transport does not support fetching `{}`", request.url)))
+        })
+    }
+}
+
+impl<T: AmfTransport + ?Sized> AmfTransport for std::sync::Arc<T> {
+    fn send(&self, request: AmfRequest) -> BoxFuture<'_, Result<AmfResponse>> {
+        (**self).send(request)
+    }
+
+    fn fetch(&self, request: AmfRequest) -> BoxFuture<'_, Result<AmfResponse>> {
+        (**self).fetch(request)
+    }
+}
+
+impl<T: AmfTransport + ?Sized> AmfTransport for Box<T> {
+    fn send(&self, request: AmfRequest) -> BoxFuture<'_, Result<AmfResponse>> {
+        (**self).send(request)
+    }
+
+    fn fetch(&self, request: AmfRequest) -> BoxFuture<'_, Result<AmfResponse>> {
+        (**self).fetch(request)
+    }
+}
+
+/// How the requests look like and how they are sent, the `[http]` section of an account.
+///
+/// `user_agent`, `flash_version` and `headers` are sent by any transport,
+/// the others only apply to the default [`ReqwestTransport`].
+///
+/// ```toml
+/// [http]
+/// user_agent = "Mozilla/5.0 (Windows NT 10.0; Win64; x64)"
+/// flash_version = "32,0,0,171"
+/// proxy = "socks5://127.0.0.1:1080"
+/// connect_timeout_ms = 5000
+/// timeout_ms = 30000
+/// keep_alive = false
+///
+/// [http.headers]
+/// Accept-Language = "zh-CN,zh;q=0.9"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HttpOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// overrides the flash version of the server.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flash_version: Option<String>,
+    /// sent with every request.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    /// an `http://` or `https://` proxy, or `socks5://` with the `socks` feature.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<Url>,
+    #[serde(rename = "connect_timeout_ms", with = "millis", skip_serializing_if = "Option::is_none")]
+    pub connect_timeout: Option<Duration>,
+    /// the timeout of the whole round trip.
+    #[serde(rename = "timeout_ms", with = "millis", skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<Duration>,
+    /// reuse connections, `true` by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<bool>,
+}
+
+impl HttpOptions {
+    pub fn is_default(&self) -> bool {
+        *self == HttpOptions::default()
+    }
+}
+
+mod millis {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(d: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+        d.map(|d| d.as_millis() as u64).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+        Ok(Option::<u64>::deserialize(d)?.map(Duration::from_millis))
+    }
+}
+
+/// The default transport.
+///
+/// Redirects are not followed, since the gateway only redirects to the login page
+/// when the session is expired.
+#[derive(Debug, Clone)]
+pub struct ReqwestTransport {
+    client: reqwest::Client,
+}
+
+impl Default for ReqwestTransport {
+    fn default() -> Self {
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("fail to build the default reqwest client");
+        ReqwestTransport { client }
+    }
+}
+
+impl ReqwestTransport {
+    pub fn new() -> Self {
+        ReqwestTransport::default()
+    }
+
+    pub fn with_client(client: reqwest::Client) -> Self {
+        ReqwestTransport { client }
+    }
+
+    /// a transport with the proxy, timeouts and keep-alive of `options`.
+    pub fn with_options(options: &HttpOptions) -> Result<Self> {
+        let mut builder = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none());
+        if let Some(proxy) = &options.proxy {
+            let proxy = reqwest::Proxy::all(proxy.as_str())
+                .map_err(|e| ErrorKind::Config(format!("invalid proxy `{}`: {}", proxy, e)))?;
+            builder = builder.proxy(proxy);
+        }
+        if let Some(timeout) = options.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = options.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if options.keep_alive == Some(false) {
+            builder = builder.pool_max_idle_per_host(0);
+        }
+        let client = builder
+            .build()
+            .map_err(|e| ErrorKind::Config(format!("fail to build the http client: {}", e)))?;
+        Ok(ReqwestTransport { client })
+    }
+}
+
+impl AmfTransport for ReqwestTransport {
+    fn send(&self, request: AmfRequest) -> BoxFuture<'_, Result<AmfResponse>> {
+        Box::pin(async move {
+            let resp = self.client
+                .post(request.url)
+                .headers(request.headers)
+                .body(request.body)
+                .send()
+                .await
+                .map_err(network_error)?;
+            into_response(resp).await
+        })
+    }
+
+    fn fetch(&self, request: AmfRequest) -> BoxFuture<'_, Result<AmfResponse>> {
+        Box::pin(async move {
+            let resp = self.client
+                .get(request.url)
+                .headers(request.headers)
+                .send()
+                .await
+                .map_err(network_error)?;
+            into_response(resp).await
+        })
+    }
+}
+
+async fn into_response(resp: reqwest::Response) -> Result<AmfResponse> {
+    let status = resp.status();
+    let headers = resp.headers().clone();
+    let body = resp
+        .bytes()
+        .await
+        .map_err(network_error)?;
+    Ok(AmfResponse {
+        status,
+        headers,
+        body,
+    })
+}
+
+/// failures of the round trip are network errors, a request which can not be built
+/// (e.g. an invalid url) is a config error.
+fn network_error(e: reqwest::Error) -> ErrorKind {
+    if e.is_connect() {
+        ErrorKind::Connect(Box::new(e))
+    } else if e.is_builder() {
+        ErrorKind::Config(e.to_string())
+    } else {
+        ErrorKind::Network(Box::new(e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{amf::{amf0::{number, object}, packet::{IntoBytes, Packet, ReadAs}, Version}, Client};
+
+    /// answer every call with `{ now_id: 2 }`, and keep the requests.
+    #[derive(Default)]
+    struct InMemory {
+        requests: Mutex<Vec<AmfRequest>>,
+    }
+
+    impl AmfTransport for InMemory {
+        fn send(&self, request: AmfRequest) -> BoxFuture<'_, Result<AmfResponse>> {
+            self.requests.lock().unwrap().push(request);
+            let resp = Packet::builder()
+                .version(Version::Amf0)
+                .body("/1/onResult", "null", object(vec![("now_id", number(2.))].into_iter()))
+                .build()
+                .unwrap();
+            Box::pin(async move { Ok(AmfResponse::ok(resp.into_bytes())) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_transport() -> Result<()> {
+        let transport = std::sync::Arc::new(InMemory::default());
+        let client = Client::builder()
+            .server(1)
+            .cookie("PHPSESSID", "abc")
+            .transport(transport.clone())
+            .build()?;
+
+        assert_eq!(client.skill_up(1., 1.).await?, 2.);
+
+        let mut requests = transport.requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        let request = &mut requests[0];
+        assert_eq!(request.url.as_str(), "http://pvz-s1.youkia.com/pvz/amf/");
+        assert_eq!(request.headers["cookie"], "PHPSESSID=abc");
+        let packet: Packet = request.body.read_as()?;
+        assert_eq!(packet.bodies[0].target_uri, "api.apiorganism.skillUp");
+        assert_eq!(packet.bodies[0].response_uri, "/1");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_http_options() -> Result<()> {
+        let account = crate::AccountInfo::from_bytes(r#"
+            server = 1
+            cookies = { PHPSESSID = "abc" }
+
+            [http]
+            user_agent = "Mozilla/5.0"
+            flash_version = "32,0,0,171"
+            timeout_ms = 30000
+
+            [http.headers]
+            Accept-Language = "zh-CN"
+            Referer = "http://pvz-s1.youkia.com/pvz/main.swf"
+        "#)?;
+        assert_eq!(account.http.timeout, Some(std::time::Duration::from_secs(30)));
+
+        let transport = std::sync::Arc::new(InMemory::default());
+        let client = Client::builder()
+            .account(account)
+            .transport(transport.clone())
+            .build()?;
+        client.skill_up(1., 1.).await?;
+
+        let requests = transport.requests.lock().unwrap();
+        let headers = &requests[0].headers;
+        assert_eq!(headers["user-agent"], "Mozilla/5.0");
+        assert_eq!(headers["x-flash-version"], "32,0,0,171");
+        assert_eq!(headers["accept-language"], "zh-CN");
+        assert_eq!(headers["referer"], "http://pvz-s1.youkia.com/pvz/main.swf");
+        assert_eq!(headers["cookie"], "PHPSESSID=abc");
+
+        let options = HttpOptions {
+            proxy: Some("ftp://127.0.0.1:21".parse().unwrap()),
+            ..Default::default()
+        };
+        assert!(matches!(ReqwestTransport::with_options(&options), Err(ErrorKind::Config(_))));
+        let options = HttpOptions {
+            proxy: Some("http://127.0.0.1:8080".parse().unwrap()),
+            keep_alive: Some(false),
+            ..Default::default()
+        };
+        assert!(ReqwestTransport::with_options(&options).is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+//! Record the traffic of a `Client` into a cassette file, and replay it later.
+//!
+//! A cassette is a toml file with an `[[interaction]]` table for every http round trip,
+//! the packets are kept as hex strings, so they are exactly the bytes on the wire.
+//! Cookies and session ids are redacted before they are recorded:
+//! - the `Cookie` request header, and the values of `Set-Cookie` response headers
+//! - `PHPSESSID` in the `AppendToGatewayUrl` packet header of the responses
+//!
+//! ```no_run
+//! # async fn f() -> pvzol_tools_lib::Result<()> {
+//! use std::sync::Arc;
+//! use pvzol_tools_lib::{Client, transport::{ReqwestTransport, cassette::*}};
+//!
+//! let recorder = Arc::new(RecordingTransport::new(ReqwestTransport::new()));
+//! let client = Client::builder().server(36).transport(recorder.clone()).build()?;
+//! // ... use the client
+//! recorder.cassette().save("skill_up.toml").await?;
+//!
+//! let replay = ReplayTransport::new(Cassette::load("skill_up.toml").await?);
+//! let client = Client::builder().server(36).transport(replay).build()?;
+//! # Ok(()) }
+//! ```
+
+use std::{collections::{BTreeMap, VecDeque}, path::Path, sync::Mutex};
+
+use bytes::Bytes;
+use reqwest::{header::{HeaderMap, HeaderName, HeaderValue, COOKIE, SET_COOKIE}, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use super::{AmfRequest, AmfResponse, AmfTransport, BoxFuture};
+use crate::{
+    amf::{amf0, packet::{IntoBytes, Packet, ReadAs}, Value},
+    ErrorKind, Result,
+};
+
+const REDACTED: &str = "<redacted>";
+
+/// Packet headers which carry the session id.
+const SESSION_HEADERS: [&str; 2] = ["AppendToGatewayUrl", "ReplaceGatewayUrl"];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cassette {
+    #[serde(rename = "interaction", default)]
+    pub interactions: Vec<Interaction>,
+}
+
+/// A recorded http round trip.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Interaction {
+    /// target uris of the calls in the request, only for reading.
+    #[serde(default)]
+    pub calls: Vec<String>,
+    pub url: String,
+    pub status: u16,
+    /// the request packet as hex.
+    pub request: String,
+    /// the response packet as hex.
+    pub response: String,
+    // tables must be put after plain values in toml.
+    #[serde(default)]
+    pub request_headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub response_headers: BTreeMap<String, String>,
+}
+
+impl Cassette {
+    pub async fn load(path: impl AsRef<Path>) -> Result<Cassette> {
+        let content = tokio::fs::read(path).await?;
+        Cassette::from_bytes(content)
+    }
+
+    pub fn from_bytes(bytes: impl AsRef<[u8]>) -> Result<Cassette> {
+        toml::from_slice(bytes.as_ref()).map_err(|e| ErrorKind::Config(format!("invalid cassette: {}", e)))
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string(self).map_err(|e| ErrorKind::Serialize(Box::new(e)))
+    }
+
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        Ok(tokio::fs::write(path, self.to_toml()?).await?)
+    }
+}
+
+impl Interaction {
+    /// record a round trip, with the cookies redacted.
+    pub fn record(request: &AmfRequest, response: &AmfResponse) -> Interaction {
+        let calls = Bytes::clone(&request.body)
+            .read_as()
+            .map(|p: Packet| p.bodies.into_iter().map(|b| b.target_uri).collect())
+            .unwrap_or_default();
+
+        Interaction {
+            calls,
+            url: request.url.to_string(),
+            status: response.status.as_u16(),
+            request: to_hex(&request.body),
+            response: to_hex(&redact_packet(&response.body)),
+            request_headers: headers_to_map(&request.headers),
+            response_headers: headers_to_map(&response.headers),
+        }
+    }
+
+    pub fn request_body(&self) -> Result<Bytes> {
+        from_hex(&self.request)
+    }
+
+    pub fn to_response(&self) -> Result<AmfResponse> {
+        let mut headers = HeaderMap::new();
+        for (k, v) in &self.response_headers {
+            let name = HeaderName::from_bytes(k.as_bytes())
+                .map_err(|e| ErrorKind::Config(format!("invalid header name in cassette: {}", e)))?;
+            let value = HeaderValue::from_str(v)
+                .map_err(|e| ErrorKind::Config(format!("invalid header value in cassette: {}", e)))?;
+            headers.append(name, value);
+        }
+        Ok(AmfResponse {
+            status: StatusCode::from_u16(self.status)
+                .map_err(|e| ErrorKind::Config(format!("invalid status in cassette: {}", e)))?,
+            headers,
+            body: from_hex(&self.response)?,
+        })
+    }
+}
+
+/// Forward requests to `inner`, and record every round trip.
+pub struct RecordingTransport<T> {
+    inner: T,
+    cassette: Mutex<Cassette>,
+}
+
+impl<T: AmfTransport> RecordingTransport<T> {
+    pub fn new(inner: T) -> Self {
+        RecordingTransport {
+            inner,
+            cassette: Mutex::new(Cassette::default()),
+        }
+    }
+
+    /// the round trips recorded so far.
+    pub fn cassette(&self) -> Cassette {
+        self.cassette.lock().unwrap().clone()
+    }
+}
+
+impl<T: AmfTransport> AmfTransport for RecordingTransport<T> {
+    fn send(&self, request: AmfRequest) -> BoxFuture<'_, Result<AmfResponse>> {
+        Box::pin(async move {
+            let response = self.inner.send(request.clone()).await?;
+            self.cassette
+                .lock()
+                .unwrap()
+                .interactions
+                .push(Interaction::record(&request, &response));
+            Ok(response)
+        })
+    }
+
+    /// fetched files are not recorded, they are large and not part of the conversation.
+    fn fetch(&self, request: AmfRequest) -> BoxFuture<'_, Result<AmfResponse>> {
+        self.inner.fetch(request)
+    }
+}
+
+/// Answer requests with the recorded responses, in the recorded order.
+///
+/// A request must have the same packet as the recorded one,
+/// otherwise the client no longer behaves like when it was recorded.
+pub struct ReplayTransport {
+    interactions: Mutex<VecDeque<Interaction>>,
+}
+
+impl ReplayTransport {
+    pub fn new(cassette: Cassette) -> Self {
+        ReplayTransport {
+            interactions: Mutex::new(cassette.interactions.into()),
+        }
+    }
+
+    /// the number of recorded round trips which are not replayed yet.
+    pub fn remaining(&self) -> usize {
+        self.interactions.lock().unwrap().len()
+    }
+}
+
+impl AmfTransport for ReplayTransport {
+    fn send(&self, request: AmfRequest) -> BoxFuture<'_, Result<AmfResponse>> {
+        let next = self.interactions.lock().unwrap().pop_front();
+        Box::pin(async move {
+            let interaction = next.ok_or_else(|| ErrorKind::Config("no more interactions in the cassette".to_owned()))?;
+            if interaction.request_body()? != request.body {
+                return Err(ErrorKind::Config(format!(
+                    "the request does not match the cassette, expect calls {:?}",
+                    interaction.calls,
+                )));
+            }
+            interaction.to_response()
+        })
+    }
+}
+
+fn headers_to_map(headers: &HeaderMap) -> BTreeMap<String, String> {
+    let mut map = BTreeMap::new();
+    for (name, value) in headers {
+        let value = if name == COOKIE {
+            REDACTED.to_owned()
+        } else if name == SET_COOKIE {
+            redact_set_cookie(value.to_str().unwrap_or_default())
+        } else {
+            value.to_str().unwrap_or_default().to_owned()
+        };
+        // headers with the same name are joined, it is enough for reading and replaying.
+        map.entry(name.as_str().to_owned())
+            .and_modify(|v: &mut String| {
+                v.push_str(", ");
+                v.push_str(&value);
+            })
+            .or_insert(value);
+    }
+    map
+}
+
+/// `name=value; Path=/` -> `name=<redacted>; Path=/`
+fn redact_set_cookie(cookie: &str) -> String {
+    match cookie.split_once('=') {
+        Some((name, rest)) => {
+            let attrs = rest.find(';').map(|i| &rest[i..]).unwrap_or_default();
+            format!("{}={}{}", name, REDACTED, attrs)
+        },
+        None => REDACTED.to_owned(),
+    }
+}
+
+/// redact the session id in the packet headers, the body is kept as is if there is nothing to redact.
+fn redact_packet(body: &Bytes) -> Bytes {
+    let mut packet: Packet = match Bytes::clone(body).read_as() {
+        Ok(packet) => packet,
+        Err(_) => return body.clone(),
+    };
+    let mut redacted = false;
+    for header in &mut packet.headers {
+        if SESSION_HEADERS.contains(&header.name.as_str()) {
+            header.data = Value::Amf0(amf0::string(format!("?PHPSESSID={}", REDACTED)));
+            redacted = true;
+        }
+    }
+    if redacted {
+        packet.into_bytes()
+    } else {
+        body.clone()
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    use std::fmt::Write;
+
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        write!(hex, "{:02x}", b).unwrap();
+    }
+    hex
+}
+
+fn from_hex(hex: &str) -> Result<Bytes> {
+    let hex: Vec<u8> = hex.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    let pairs = hex.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return Err(ErrorKind::Config("invalid hex in cassette: odd length".to_owned()));
+    }
+    pairs
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok())
+                .ok_or_else(|| ErrorKind::Config(format!("invalid hex in cassette: `{}`", String::from_utf8_lossy(pair))))
+        })
+        .collect::<Result<Vec<u8>>>()
+        .map(Bytes::from)
+}
@@ -1,7 +1,6 @@
-use std::str::FromStr;
 
 use clap::{Subcommand};
-use lib::{game::sys::{Quality, QualityUpType}, Client, Result};
+use lib::{game::{sys::{Quality, QualityUpType}, GameUser}, Client, ErrorKind, Result};
 
 macro_rules! warn_ignored {
     ($lit:literal) => {
@@ -13,6 +12,9 @@ macro_rules! warn_ignored {
 #[non_exhaustive]
 pub(crate) enum Command {
 
+    /// 查看账号状态 (需要配置`sig_key`)
+    Status,
+
     /// 刷新品质
     QualityUp {
         /// 目标品质
@@ -99,19 +101,16 @@ pub(crate) enum HackCommand {
     },
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub(crate) enum OrderBy {
-    Name,
-    Level,
-}
-
 impl Command {
     pub async fn invoke_on(self, client: &Client, repeat: Option<usize>) -> Result<()> {
         use Command::*;
 
-        let repeat_times = repeat.unwrap_or(1) as usize;
+        let repeat_times = repeat.unwrap_or(1);
+        // the loops are stopped by the token of the client, which is cancelled on Ctrl-C.
+        let cancel = client.cancellation_token();
 
         match self {
+            Status => print_status(&client.refresh_user().await?),
             QualityUp {
                 plant_id: plant_ids,
                 until,
@@ -136,7 +135,7 @@ impl Command {
                     None => Box::new(move |i,_| i >= repeat_times),
                 };
                 for plant_id in plant_ids {
-                    client.quality_up_to(quality_up_type, plant_id, &until_fn).await?;
+                    client.quality_up_to(quality_up_type, plant_id, &until_fn, cancel).await?;
                 }
             },
             SkillUp {
@@ -153,25 +152,26 @@ impl Command {
                     },
                     None => Box::new(move |i,_| i >= repeat_times),
                 };
-                client.skill_up_to(plant_id, skill_id, until).await?;
+                client.skill_up_to(plant_id, skill_id, until, cancel).await?;
             },
             Open { box_id, amount } => {
                 let amount = amount.unwrap_or(1);
                 if amount == 0 {
-                    return Err("单次开启数量必须大于1且小于11".into());
+                    return Err(ErrorKind::Config("单次开启数量必须大于1且小于11".to_owned()));
                 } else if amount > 10 {
-                    return Err("单次开启数量必须小于11, 如果想开启多个, 请使用`--repeat`参数".into());
+                    return Err(ErrorKind::Config("单次开启数量必须小于11, 如果想开启多个, 请使用`--repeat`参数".to_owned()));
                 }
-                client.open_box_repeat(box_id, amount, repeat_times).await?;
+                let opened = client.open_box_repeat(box_id, amount, repeat_times, cancel).await?;
+                println!("共开启{}个", opened);
             },
             Challenge {is_fuben, is_stone, id: fuben_id, plant_ids } => {
                 if is_fuben {
-                    client.challenge_fuben_repeat(fuben_id, plant_ids, repeat_times).await?;
+                    client.challenge_fuben_repeat(fuben_id, plant_ids, repeat_times, cancel).await?;
                 } else if is_stone {
                     // compile_error!("unimplement");
-                    return Err("该功能未完成".into());
+                    return Err(ErrorKind::Unsupported("该功能未完成".to_owned()));
                 } else {
-                    return Err("未给定挑战类型.(公洞/个洞/按洞/副本/...)".into());
+                    return Err(ErrorKind::Config("未给定挑战类型.(公洞/个洞/按洞/副本/...)".to_owned()));
                 }
             },
             #[cfg(feature = "hack")]
@@ -183,10 +183,25 @@ impl Command {
     }
 }
 
+impl Command {
+    /// whether the command spends money, so the account status is checked before.
+    pub fn is_automation(&self) -> bool {
+        !matches!(self, Command::Status)
+    }
+}
+
+pub(crate) fn print_status(user: &GameUser) {
+    println!("{} (Id: {}) 等级{} 经验{} VIP{}", user.name, user.id, user.grade, user.exp, user.vip_grade);
+    println!("金币: {}  钻石: {}  荣誉: {}", user.money, user.diamonds, user.honor);
+    println!("今日剩余挑战: 副本{}次, 宝石副本{}次", user.challenges.fuben, user.challenges.stone);
+}
+
 #[cfg(feature = "hack")]
 impl HackCommand {
     pub async fn invoke_on(self, client: &Client, repeat: Option<usize>) -> Result<()> {
         let repeat_times = repeat.unwrap_or(1);
+        // the loops are stopped by the token of the client, which is cancelled on Ctrl-C.
+        let cancel = client.cancellation_token();
         match self {
             HackCommand::Duty { duty_ids } => {
                 if repeat.is_some() {
@@ -201,34 +216,10 @@ impl HackCommand {
                     }
                     client.reset_fuben_reward(fuben_id).await?;
                 } else {
-                    client.reset_and_get_fuben_reward(fuben_id, repeat_times).await?;
+                    client.reset_and_get_fuben_reward(fuben_id, repeat_times, cancel).await?;
                 }
             },
         }
         Ok(())
     }
 }
-
-impl std::fmt::Display for OrderBy {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        <Self as std::fmt::Debug>::fmt(&self, f)
-    }
-}
-
-impl FromStr for OrderBy {
-    type Err = String;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        use OrderBy::*;
-
-        if s.len() > 5 || !s.is_ascii() {
-            return Err(format!("fail to parse `{}` as OrderBy", s));
-        }
-
-        let lower = s.to_ascii_lowercase();
-        match lower.as_str() {
-            "n" | "name" => Ok(Name),
-            "l" | "level" => Ok(Level),
-            _ => Err(format!("fail to parse `{}` as OrderBy", s)),
-        }
-    }
-}
@@ -11,13 +11,20 @@
 //!   2. 自动合成、滚包(需要准备好材料)
 //!  
 
-use std::path::PathBuf;
+use std::{io::Write, path::PathBuf, sync::{Arc, Mutex}, time::Duration};
 
 use clap::{AppSettings, ArgGroup, Parser};
 use command::Command;
-use lib::{Client, AccountInfo, Result, ErrorKind};
+use lib::{Client, AccountInfo, Result, ErrorKind, Rejection};
+use lib::cancel::CancellationToken;
+use lib::event::{Event, Progress};
+use lib::game::sys::SysCache;
+use lib::pacing::{Pacing, Rate};
+use lib::retry::RetryPolicy;
+use lib::transport::{cassette::RecordingTransport, ReqwestTransport};
 
 mod command;
+mod report;
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
@@ -36,6 +43,42 @@ struct Cli {
     #[clap(short, long, value_parser, value_name = "FILE_NAME")]
     config: Option<PathBuf>,
 
+    /// 将本次运行的请求和响应记录到文件 (cookie会被隐去), 用于反馈问题
+    #[clap(long, value_parser, value_name = "FILE")]
+    record: Option<PathBuf>,
+
+    /// 两次请求之间的间隔毫秒数, 如`800-1400` (默认) 或 `0`
+    #[clap(long, value_parser = parse_interval, value_name = "MIN[-MAX]")]
+    interval: Option<(Duration, Duration)>,
+
+    /// 每秒最多发送的请求数
+    #[clap(long, value_parser, value_name = "N")]
+    rate: Option<f64>,
+
+    /// 同一服务器每秒最多发送的请求数
+    #[clap(long, value_parser, value_name = "N")]
+    server_rate: Option<f64>,
+
+    /// 连接失败时最多尝试的次数 (包括第一次), 默认为3; 请求发出后的失败不会重试, 以免重复消耗
+    #[clap(long, value_name = "ATTEMPTS", value_parser = clap::value_parser!(u32).range(1..))]
+    retry: Option<u32>,
+
+    /// 游戏数据 (tool.xml等) 的缓存目录, 默认为配置文件旁的`cache`目录
+    #[clap(long, value_parser, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// 只使用缓存的游戏数据, 不下载
+    #[clap(long, action)]
+    offline: bool,
+
+    /// 将服务器更新的cookie写回配置文件 (只修改`[cookies]`中的值)
+    #[clap(long, action)]
+    save_cookies: bool,
+
+    /// 金币低于该值时不开始执行 (需要配置`sig_key`)
+    #[clap(long, value_parser, value_name = "MONEY")]
+    min_money: Option<u64>,
+
     /// 重复执行次数 (仅对某些命令有效)
     #[clap(long = "repeat", value_name = "TIMES", value_parser = clap::value_parser!(u64).range(1..))]
     repeat_times: Option<u64>,
@@ -56,17 +99,189 @@ async fn main_wrapped() -> Result<(), ErrorKind> {
 
     let config_file = cli.config
         .or(cli.user.map(Into::into))
-        .ok_or("必须给定用户信息")?;
+        .ok_or_else(|| ErrorKind::Config("必须给定用户信息".to_owned()))?;
 
     if !config_file.exists() {
-        return  Err(format!("找不到给定的配置文件\"{:?}\"", config_file.as_os_str()).into());
+        return Err(ErrorKind::Config(format!("找不到给定的配置文件{:?}", config_file.as_os_str())));
+    }
+
+    let mut pacing = Pacing::default();
+    if let Some((min, max)) = cli.interval {
+        pacing = pacing.interval(min, max);
+    }
+    if cli.rate.into_iter().chain(cli.server_rate).any(|r| r <= 0.) {
+        return Err(ErrorKind::Config("`--rate`和`--server-rate`必须大于0".to_owned()));
+    }
+    if let Some(rate) = cli.rate {
+        pacing = pacing.rate(Rate::per_second(rate));
+    }
+    if let Some(rate) = cli.server_rate {
+        pacing = pacing.server_rate(Rate::per_second(rate));
+    }
+
+    let cache_dir = cli.cache_dir.unwrap_or_else(|| {
+        config_file.parent().unwrap_or_else(|| ".".as_ref()).join("cache")
+    });
+
+    let account = AccountInfo::from_file(&config_file).await?;
+    let report = Arc::new(Mutex::new(report::Report::default()));
+    let cancel = CancellationToken::new();
+    tokio::spawn(handle_ctrl_c(cancel.clone()));
+    let mut builder = Client::builder()
+        .account(account.clone())
+        .pacing(pacing)
+        .retry(RetryPolicy::default().attempts(cli.retry.unwrap_or(3)))
+        .on_event({
+            let report = report.clone();
+            move |event| {
+                report.lock().unwrap().record(event);
+                print_event(event);
+            }
+        })
+        .cancellation(cancel.clone())
+        .sys_cache(SysCache::new(cache_dir).offline(cli.offline))
+        .reauth({
+            // reload the cookies, in case they are updated in the config file.
+            let config_file = config_file.clone();
+            move || {
+                let config_file = config_file.clone();
+                async move { Ok(AccountInfo::from_file(config_file).await?.cookies) }
+            }
+        });
+    let recorder = cli.record.as_ref().map(|_| {
+        Arc::new(RecordingTransport::new(ReqwestTransport::new()))
+    });
+    if let Some(recorder) = &recorder {
+        builder = builder.transport(recorder.clone());
+    }
+    let client = builder.build()?;
+
+    // the status needs the sig key, so it is only checked if it can be. It is only required
+    // by `--min-money`, otherwise a failure does not stop the command.
+    let check_status = cli.command.is_automation() && (account.sig_key.is_some() || cli.min_money.is_some());
+    if check_status {
+        match (client.refresh_user().await, cli.min_money) {
+            (Ok(user), min_money) => {
+                command::print_status(&user);
+                if let Some(min_money) = min_money.filter(|&min| user.money < min) {
+                    return Err(ErrorKind::GameRejected {
+                        reason: Rejection::NotEnoughMoney,
+                        description: format!("金币不足: 剩余{}, 至少需要{}", user.money, min_money),
+                        code: None,
+                    });
+                }
+                println!();
+            },
+            (Err(e), Some(_)) => return Err(e),
+            (Err(e), None) => eprintln!("warning: 无法获取账号状态: {}", e),
+        }
+    }
+
+    let res = cli.command.invoke_on(&client, cli.repeat_times.map(|n| n as usize)).await;
+    report.lock().unwrap().print();
+    if let (true, Some(before)) = (check_status, client.user()) {
+        match client.refresh_user().await {
+            Ok(after) => println!("金币: {} -> {}", before.money, after.money),
+            Err(e) => eprintln!("warning: 无法获取账号状态: {}", e),
+        }
+    }
+
+    // save the record even if the command fails, which is usually when it is needed.
+    if let (Some(path), Some(recorder)) = (cli.record, recorder) {
+        recorder.cassette().save(&path).await?;
+        eprintln!("已记录到{:?}", path.as_os_str());
+    }
+
+    // keep the cookies refreshed by the server, so the config file does not go stale.
+    let refreshed = client.account_info();
+    if cli.save_cookies && refreshed.cookies != account.cookies {
+        refreshed.save_cookies(&config_file).await?;
+    }
+
+    res
+}
+
+/// stop the loops after the request in flight on the first Ctrl-C, and exit at once on the second.
+async fn handle_ctrl_c(cancel: CancellationToken) {
+    if tokio::signal::ctrl_c().await.is_err() {
+        return;
+    }
+    eprintln!("\nwarning: 正在停止, 等待当前请求完成 (再按一次Ctrl-C强制退出)");
+    cancel.cancel();
+    if tokio::signal::ctrl_c().await.is_ok() {
+        std::process::exit(130);
     }
+}
 
-    let client = Client::builder()
-        .account(AccountInfo::from_file(config_file).await?)
-        .build()?;
+fn print_event(event: &Event) {
+    match event {
+        Event::Retry { attempt, delay, error } => {
+            eprintln!("warning: 第{}次请求失败({}), {:.1}秒后重试", attempt, error, delay.as_secs_f64());
+        },
+        Event::Reauth { reason } => {
+            eprintln!("warning: 登录已失效({}), 重新读取配置文件中的cookie", reason);
+        },
+        Event::Progress(progress) => print_progress(progress),
+        _ => {},
+    }
+}
 
-    cli.command.invoke_on(&client, cli.repeat_times.map(|n| n as usize)).await?;
+fn print_progress(progress: &Progress) {
+    match progress {
+        Progress::QualityUp { plant_id, attempt, old, new } => {
+            if *attempt == 1 {
+                println!("------ START {} ------", plant_id);
+            }
+            if old.is_some_and(|old| old != *new) {
+                println!("\rtry {:-3} : -> {} !", attempt, new);
+            } else {
+                print!("\rtry {:-3} : {}", attempt, new);
+            }
+        },
+        Progress::SkillUp { attempt, old, new, .. } => {
+            if old != new {
+                println!("\rtry {:-3} : {} -> {} !", attempt, old, new);
+            } else {
+                print!("\rtry {:-3} : {}", attempt, new);
+            }
+        },
+        Progress::BoxOpened { round, amount, .. } => {
+            println!("\rNo.{:-4 } 成功开启{}个", round, amount);
+        },
+        Progress::DutyReward { duty_id, category_id, error } => match error {
+            Some(e) => eprintln!("{}", e),
+            None => println!("get reward : {:-5} in {}", duty_id, category_id),
+        },
+        Progress::FubenMedals { medals, .. } => println!("--- current medals: {}", medals),
+        Progress::FubenReset { round, .. } => print!("No.{:-3} : reset", round),
+        Progress::FubenAward { attempt, done, .. } => {
+            print!(" : get-{}", attempt);
+            if *done {
+                println!(" : ok");
+            }
+        },
+        Progress::Challenged { round, win, .. } => println!("repeat {:-3} : win={}", round, win),
+        Progress::Stopped { message, .. } => println!("\nstop: {}", message),
+        Progress::Cancelled => println!("\ncancelled"),
+        _ => {},
+    }
+    let _ = std::io::stdout().flush();
+}
 
-    Ok(())
+/// `MIN[-MAX]` in milliseconds
+fn parse_interval(s: &str) -> Result<(Duration, Duration), String> {
+    let parse = |ms: &str| {
+        ms.trim()
+            .parse()
+            .map(Duration::from_millis)
+            .map_err(|_| format!("无法将`{}`解析为毫秒数", ms))
+    };
+    let (min, max) = match s.split_once('-') {
+        Some((min, max)) => (parse(min)?, parse(max)?),
+        None => (parse(s)?, parse(s)?),
+    };
+    if min > max {
+        return Err(format!("最小间隔不能大于最大间隔: `{}`", s));
+    }
+    Ok((min, max))
 }
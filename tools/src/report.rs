@@ -0,0 +1,88 @@
+use lib::{event::{Event, Progress}, game::sys::Quality};
+
+/// What a run achieved, collected from the progress events and printed when the run ends.
+#[derive(Debug, Default)]
+pub(crate) struct Report {
+    /// requests of the loops
+    attempts: usize,
+    quality: Option<Quality>,
+    skill_id: Option<f64>,
+    /// 品质刷新书/魔神刷新书
+    quality_books: usize,
+    /// 技能书
+    skill_books: usize,
+    boxes: usize,
+    fuben_rounds: usize,
+    challenges: usize,
+    wins: usize,
+    stopped: Option<String>,
+    cancelled: bool,
+}
+
+impl Report {
+    pub fn record(&mut self, event: &Event) {
+        let progress = match event {
+            Event::Progress(progress) => progress,
+            _ => return,
+        };
+        match progress {
+            Progress::QualityUp { new, .. } => {
+                self.attempts += 1;
+                self.quality_books += 1;
+                self.quality = Some(*new);
+            },
+            Progress::SkillUp { new, .. } => {
+                self.attempts += 1;
+                self.skill_books += 1;
+                self.skill_id = Some(*new);
+            },
+            Progress::BoxOpened { amount, .. } => {
+                self.attempts += 1;
+                self.boxes += *amount as usize;
+            },
+            Progress::FubenReset { .. } => {
+                self.attempts += 1;
+                self.fuben_rounds += 1;
+            },
+            Progress::FubenAward { .. } => self.attempts += 1,
+            Progress::Challenged { win, .. } => {
+                self.attempts += 1;
+                self.challenges += 1;
+                self.wins += *win as usize;
+            },
+            Progress::Stopped { message, .. } => self.stopped = Some(message.clone()),
+            Progress::Cancelled => self.cancelled = true,
+            _ => {},
+        }
+    }
+
+    pub fn print(&self) {
+        if self.attempts == 0 {
+            return;
+        }
+        println!();
+        println!("------ 统计 ------");
+        if self.cancelled {
+            println!("已中断");
+        }
+        if let Some(reason) = &self.stopped {
+            println!("提前结束: {}", reason);
+        }
+        println!("请求次数: {}", self.attempts);
+        if let Some(quality) = self.quality {
+            println!("最终品质: {} (消耗刷新书{}本)", quality, self.quality_books);
+        }
+        if let Some(skill_id) = self.skill_id {
+            println!("最终技能: {} (消耗技能书{}本)", skill_id, self.skill_books);
+        }
+        if self.boxes > 0 {
+            println!("开启箱子: {}个", self.boxes);
+        }
+        if self.fuben_rounds > 0 {
+            println!("重置副本: {}次", self.fuben_rounds);
+        }
+        if self.challenges > 0 {
+            println!("挑战: {}次, 胜利{}次", self.challenges, self.wins);
+        }
+    }
+}